@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+mod l_system;
+use crate::l_system::{fractal_plant, produce};
+
+// Compares the string based `produce` with `CompiledRules` on the fractal plant.
+// Run with optimisations, otherwise the numbers are meaningless:
+//     cargo run --release --example bench_produce
+// With `--features parallel` the parallel compiled production is timed as well.
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+fn throughput(symbols: usize, duration: Duration) -> f64 {
+    symbols as f64 / duration.as_secs_f64() / 1e6
+}
+
+fn main() {
+    let l_system = fractal_plant();
+    let (compiled, axiom) = l_system.compile();
+
+    println!("{:>10} {:>12} {:>22} {:>22} {:>8}", "iterations", "symbols", "produce", "compiled", "speedup");
+
+    for &iterations in [6, 8, 10].iter() {
+        let (string, string_time) = time(|| {
+            let mut production = l_system.axiom.clone();
+            for _ in 0..iterations {
+                production = produce(&production, &l_system.production_rules);
+            }
+            production
+        });
+
+        let (buffer, compiled_time) = time(|| compiled.derive(&axiom, iterations));
+
+        let symbols = string.chars().count();
+        assert_eq!(symbols, buffer.len(), "both productions have to yield the same word");
+
+        println!(
+            "{:>10} {:>12} {:>9.1?} {:>6.1} Msym/s {:>9.1?} {:>6.1} Msym/s {:>7.1}x",
+            iterations,
+            symbols,
+            string_time,
+            throughput(symbols, string_time),
+            compiled_time,
+            throughput(symbols, compiled_time),
+            string_time.as_secs_f64() / compiled_time.as_secs_f64(),
+        );
+
+        #[cfg(feature = "parallel")]
+        {
+            let (parallel, parallel_time) = time(|| compiled.derive_parallel(&axiom, iterations));
+            assert_eq!(buffer, parallel, "parallel production has to match the sequential one");
+            println!(
+                "{:>10} {:>12} {:>22} {:>9.1?} {:>6.1} Msym/s {:>7.1}x  (parallel)",
+                "",
+                "",
+                "",
+                parallel_time,
+                throughput(symbols, parallel_time),
+                string_time.as_secs_f64() / parallel_time.as_secs_f64(),
+            );
+        }
+    }
+}
@@ -11,9 +11,14 @@ pub fn capture_folder_path(app: &App) -> std::path::PathBuf {
 }
 
 pub fn capture_path_timestamp(app: &App) -> std::path::PathBuf {
+    export_path_timestamp(app, "png")
+}
+
+// Same folder and naming as captures, for files written by exporters
+pub fn export_path_timestamp(app: &App, extension: &str) -> std::path::PathBuf {
     capture_folder_path(app)
             .join(escaped_timestamp())
-            .with_extension("png")
+            .with_extension(extension)
 }
 
 pub fn capture_path_frame_count(app: &App, frame: &Frame) -> std::path::PathBuf {
@@ -0,0 +1,32 @@
+use nannou::prelude::*;
+
+use crate::turtle::{Element, Geometry};
+
+// Only the viewers draw with nannou, the turtle and `lsys` don't depend on it
+impl Geometry {
+    // Draws the projection onto the xy plane, back to front
+    pub fn draw(&self, draw: &Draw) {
+        for element in self.elements() {
+            match element {
+                Element::Polygon(polygon) => {
+                    draw.polygon()
+                        .points(polygon.vertices.iter().map(|v| vec2(v.x, v.y)))
+                        .color(polygon.color);
+                }
+                Element::Segment(segment) => {
+                    draw.line()
+                        .start(vec2(segment.start.x, segment.start.y))
+                        .end(vec2(segment.end.x, segment.end.y))
+                        .weight(segment.thickness)
+                        .color(segment.color);
+                }
+                Element::Dot(dot) => {
+                    draw.ellipse()
+                        .x_y(dot.position.x, dot.position.y)
+                        .radius(dot.radius)
+                        .color(dot.color);
+                }
+            }
+        }
+    }
+}
@@ -3,56 +3,30 @@ use nannou::prelude::*;
 use nannou::math::Deg;
 
 mod l_system;
-pub use crate::l_system::produce;
+pub use crate::l_system::{produce, Symbol};
 mod turtle;
-pub use crate::turtle::Turtle;
+pub use crate::turtle::{Turtle, TurtleInterpreter};
+mod draw;
 
 fn to_rad(deg: f32) -> f32 {
     PI/180.0*deg
 }  
 
-pub fn render_turtle(draw: &Draw, path: &str) {
-    let mut turtle = Turtle{
+fn turtle() -> Turtle {
+    Turtle{
         position: vec3(0.0, -512.0, 0.0).into(),
         thickness: 2.0,
+        // − means "turn right 25°", + means "turn left 25°"
+        turn_angle: Deg(25.0),
+        // F means "draw forward"
+        line_length: 5.0,
         .. Turtle::default()
-    };
-
-    let scaling = 1.0;
-
-    for c in path.chars() {
-        match c.to_string().as_str() {
-            // F means "draw forward"
-            "F" => {
-                turtle.forward(draw, 5.0 * scaling);
-            }
-            // − means "turn right 25°"
-            "-" => {
-                turtle.yaw(Deg(25.0));
-            }
-            // + means "turn left 25°"
-            "+" => {
-                turtle.yaw(Deg(-25.0));
-            }
-            // X does not correspond to any drawing action and is used to control the evolution of the curve. 
-            "X" => {}
-            // [ save current values for position and angle
-            "[" => {
-                turtle.push();
-            }
-            // ]: pop position and angle
-            "]" => {
-                turtle.pop();
-            }
-            _ => {
-                println!("unknown command")
-            }
-        }
     }
 }
 
 struct Model {
     path: String,
+    interpreter: TurtleInterpreter,
 }
 
 fn model(app: &App) -> Model {
@@ -69,6 +43,7 @@ fn model(app: &App) -> Model {
 
     Model { 
         path: axiom,
+        interpreter: TurtleInterpreter::default(),
     }
 }
 
@@ -80,16 +55,13 @@ fn view(app: &App, model: &Model, frame: Frame) {
     
     draw.background().color(WHITE);
 
-    let mut path_substring = String::new();
-
-    for (i, c) in model.path.chars().enumerate() {
-        if (i as u64) >= app.elapsed_frames() * 8 {
-            break
-        }
-        path_substring = path_substring + &c.to_string();
-    }
+    // Draw a few more symbols every frame
+    let path = model.path
+        .chars()
+        .take((app.elapsed_frames() * 8) as usize)
+        .map(Symbol::from);
 
-    render_turtle(&draw, &path_substring);
+    model.interpreter.interpret(turtle(), path).draw(&draw);
     draw.text(&app.fps().to_string()).x_y(-500.0, 500.0);
     draw.to_frame(app, &frame).unwrap();
 }
@@ -1,73 +1,94 @@
-use std::collections::HashMap;
 use nannou::prelude::*;
 use nannou::math::Deg;
 
 mod l_system;
-pub use crate::l_system::produce;
+pub use crate::l_system::{Palette, Symbol, TimedModule, TimedRules};
 mod turtle;
-pub use crate::turtle::Turtle;
+pub use crate::turtle::{Turtle, TurtleInterpreter};
+mod draw;
 
-pub fn render_turtle(draw: &Draw, path: &str) {
-    let mut turtle = Turtle{
+// Generations per second
+const GROWTH_RATE: f32 = 1.0;
+// Start over once the tree is this old
+const MAX_AGE: f32 = 8.0;
+
+fn turtle(scaling: f32) -> Turtle {
+    Turtle{
         position: vec3(0.0, -512.0, 0.0).into(),
         thickness: 5.0,
-        color: STEELBLUE,
+        turn_angle: Deg(PI/8.0),
+        line_length: 5.0 * scaling,
+        // Leaves, then wood
+        palette: Some(Palette::Indexed(vec![GREEN, BROWN])),
         .. Turtle::default()
-    };
-
-    let scaling = 10.0/(path.len() as f32).sqrt();
-
-    for c in path.chars() {
-        match c.to_string().as_str() {
-            // 0: draw a line segment ending in a leaf
-            "0" => {
-                turtle.color = GREEN;
-                turtle.forward(draw, 5.0 * scaling);
-            }
-            // 1: draw a line segment
-            "1" => {
-                turtle.color = BROWN;
-                turtle.forward(draw, 10.0 * scaling);
-            }
-            // [: push position and angle, turn left 45 degrees
-            "[" => {
-                turtle.push();
-                turtle.yaw(Deg(-PI/8.0));
-            }
-            // ]: pop position and angle, turn right 45 degrees
-            "]" => {
-                turtle.pop();
-                turtle.yaw(Deg(PI/8.0));
-            }
-            _ => {
-                println!("unknown command")
-            }
-        }
     }
 }
 
+// Every command gets the growth of its module as the only parameter
+fn interpreter() -> TurtleInterpreter {
+    let mut interpreter = TurtleInterpreter::empty();
+    // 0: draw a line segment ending in a leaf, growing from nothing
+    interpreter.register('0', |turtle, growth| {
+        turtle.color_index = 0;
+        turtle.forward(turtle.grown_length(growth[0]));
+    });
+    // 1: draw a line segment, doubling its length until it splits in two
+    interpreter.register('1', |turtle, growth| {
+        turtle.color_index = 1;
+        turtle.forward(turtle.line_length * 2f32.powf(growth[0]));
+    });
+    // [: push position and angle, turn left 45 degrees
+    interpreter.register('[', |turtle, growth| {
+        turtle.push();
+        turtle.yaw(-turtle.grown_angle(growth[0]));
+    });
+    // ]: pop position and angle, turn right 45 degrees
+    interpreter.register(']', |turtle, growth| {
+        turtle.pop();
+        turtle.yaw(turtle.grown_angle(growth[0]));
+    });
+    interpreter
+}
+
 struct Model {
-    path: String,
-    rules: HashMap<String, String>,
+    axiom: Vec<TimedModule>,
+    path: Vec<TimedModule>,
+    rules: TimedRules,
+    interpreter: TurtleInterpreter,
+    // In generations
+    age: f32,
 }
 
 fn model(app: &App) -> Model {
     let _window = app.new_window().size(1024,1024).view(view).build().unwrap();
 
-    let axiom = String::from("0");
-    let mut production_rules = HashMap::new();
-    production_rules.insert(String::from("0"), String::from("1[0]0"));
-    production_rules.insert(String::from("1"), String::from("11"));
+    let axiom = vec![TimedModule::from(Symbol::from('0'))];
+    let word = |s: &str| s.chars().map(|c| TimedModule::from(Symbol::from(c))).collect();
+    let mut rules = TimedRules::default();
+    rules.rule(Symbol::from('0'), 1.0, word("1[0]0")).unwrap();
+    rules.rule(Symbol::from('1'), 1.0, word("11")).unwrap();
+    // Branches open up over one generation
+    rules.lifetime(Symbol::from('['), 1.0).unwrap();
+    rules.lifetime(Symbol::from(']'), 1.0).unwrap();
 
     Model { 
-        path: axiom,
-        rules: production_rules,
+        path: axiom.clone(),
+        axiom,
+        rules,
+        interpreter: interpreter(),
+        age: 0.0,
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
-    if app.elapsed_frames() % 60 == 0{
-        model.path = produce(&model.path, &model.rules)
+fn update(_app: &App, model: &mut Model, update: Update) {
+    let dt = update.since_last.as_secs_f32() * GROWTH_RATE;
+    if model.age + dt > MAX_AGE {
+        model.age = 0.0;
+        model.path = model.axiom.clone();
+    }
+    else {
+        model.age += dt;
+        model.path = model.rules.advance(&model.path, dt);
     }
 }
 
@@ -75,12 +96,18 @@ fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
     
     draw.background().color(BLACK);
-    println!("Rendering path '{}", model.path);
-    render_turtle(&draw, &model.path);
+    // The trunk doubles in length every generation, zoom out just as fast
+    let scaling = 80.0 / 2f32.powf(model.age);
+    let mut turtle = turtle(scaling);
+    for module in model.path.iter() {
+        let growth = model.rules.growth(module);
+        model.interpreter.execute(module.symbol, &mut turtle, &[growth]);
+    }
+    turtle.geometry.draw(&draw);
 
     draw.to_frame(app, &frame).unwrap();
 }
 
 fn main() {
     nannou::app(model).update(update).run();
-}
\ No newline at end of file
+}
@@ -1,5 +1,25 @@
-use std::collections::HashMap;
-use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+pub mod symbol;
+pub use symbol::{Alphabet, Symbol, produce_symbols};
+pub mod compiled;
+pub use compiled::{CompiledRules, Derivation};
+pub mod parametric;
+pub use parametric::{Module, ParametricRule, ParseError, parse_modules, produce_parametric};
+pub mod stochastic;
+pub use stochastic::{StochasticRules, StochasticRuleError, produce_stochastic_rules};
+pub mod timed;
+pub use timed::{TimedModule, TimedRuleError, TimedRules};
+pub mod homomorphism;
+pub use homomorphism::{DepthLimitedRules, Homomorphism, decompose, decompose_modules};
+pub mod palette;
+pub use palette::{Palette, Rgb8, parse_color};
+pub mod grammar;
+pub use grammar::{GrammarError, load_grammar, parse_grammar};
 
 pub fn produce(axiom: &str, rules: &HashMap<String, String>) -> String {
     let mut s = String::new();
@@ -19,43 +39,473 @@ pub fn produce(axiom: &str, rules: &HashMap<String, String>) -> String {
     s
 }
 
-pub fn produce_stochastic(axiom: &str, rules: &HashMap<String, Vec<(f32, String)>>) -> String {
-    let mut s = String::new();
-    let mut rng = rand::thread_rng();
+pub fn produce_stochastic(axiom: &str, rules: &StochasticRules) -> String {
+    produce_stochastic_rules(axiom, rules, &mut rand::thread_rng())
+}
 
-    for var in axiom.chars() {
-        match rules.get::<str>(&var.to_string()) {
-            Some(choices) => {
-                // Variables are subsituted according to a production rule 
-                // randomly chosen from the given choices
-                let random_val: f32 = rng.gen(); 
-                let mut total_prob = 0.0;
-                for (prob, string) in choices {
-                    total_prob += prob;
-                    if total_prob > 1.0 {
-                        // panic
-                        println!("Total probability is greater than 1.0, aborting");
-                        break;
+// ChaCha8 produces the same stream on every platform and rand version,
+// so a seed is enough to regenerate a stochastic plant.
+pub fn seeded_rng(seed: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+pub fn produce_stochastic_seeded(axiom: &str, rules: &StochasticRules, seed: u64) -> String {
+    produce_stochastic_rules(axiom, rules, &mut seeded_rng(seed))
+}
+
+// Context-sensitive production `left < predecessor > right -> successor` (ABOP chapter 1.8).
+// An empty context always matches, so 1L-systems simply leave one side empty.
+// `successor` is a `String` for deterministic and `StochasticRules` for stochastic
+// rules, see `ContextRule::stochastic`.
+pub struct ContextRule<S> {
+    pub left: String,
+    pub predecessor: char,
+    pub right: String,
+    pub successor: S,
+}
+
+impl ContextRule<StochasticRules> {
+    // Successors with relative weights, validated like `StochasticRules::from_weights`
+    pub fn stochastic(
+        left: &str,
+        predecessor: char,
+        right: &str,
+        weights: Vec<(f32, String)>,
+    ) -> Result<ContextRule<StochasticRules>, StochasticRuleError> {
+        let mut rules = HashMap::new();
+        rules.insert(predecessor.to_string(), weights);
+        let successor = StochasticRules::from_weights(rules)?;
+        Ok(ContextRule::new(left, predecessor, right, successor))
+    }
+}
+
+impl<S> ContextRule<S> {
+    pub fn new(left: &str, predecessor: char, right: &str, successor: S) -> ContextRule<S> {
+        ContextRule {
+            left: String::from(left),
+            predecessor,
+            right: String::from(right),
+            successor,
+        }
+    }
+
+    // Symbols in `ignore` (usually geometric ones like `+-F`) are skipped while matching
+    fn matches(&self, symbols: &[char], index: usize, ignore: &str) -> bool {
+        symbols[index] == self.predecessor
+            && left_context_matches(symbols, index, &self.left, ignore)
+            && right_context_matches(symbols, index, &self.right, ignore)
+    }
+}
+
+// Walks to the left of `index`. Bracketed sub-branches are skipped entirely and
+// a `[` is stepped over, since the symbol before it is the parent of the branch.
+fn left_context_matches(symbols: &[char], index: usize, context: &str, ignore: &str) -> bool {
+    let mut i = index;
+    for expected in context.chars().rev() {
+        loop {
+            if i == 0 {
+                return false;
+            }
+            i -= 1;
+            match symbols[i] {
+                '[' => {}
+                ']' => {
+                    // Skip back to the matching '['
+                    let mut depth = 1;
+                    while depth > 0 {
+                        if i == 0 {
+                            return false;
+                        }
+                        i -= 1;
+                        match symbols[i] {
+                            '[' => depth -= 1,
+                            ']' => depth += 1,
+                            _ => {}
+                        }
                     }
-                    if random_val < total_prob {
-                        s.push_str(string);
-                        break;
+                }
+                c if ignore.contains(c) => {}
+                c => {
+                    if c != expected {
+                        return false;
                     }
+                    break;
                 }
             }
-            None => {
-                // Constants are simply kept 
-                s.push_str(&var.to_string())
+        }
+    }
+    true
+}
+
+// Walks to the right of `index`. Sub-branches that the context does not descend
+// into are skipped, and a `]` in the context skips to the end of the current branch.
+fn right_context_matches(symbols: &[char], index: usize, context: &str, ignore: &str) -> bool {
+    let mut i = index + 1;
+    for expected in context.chars() {
+        if expected == ']' {
+            let mut depth = 1;
+            while depth > 0 {
+                match symbols.get(i) {
+                    Some('[') => depth += 1,
+                    Some(']') => depth -= 1,
+                    Some(_) => {}
+                    None => return false,
+                }
+                i += 1;
             }
+            continue;
         }
-    };
+        loop {
+            match symbols.get(i) {
+                None => return false,
+                Some(&c) if c == expected => {
+                    i += 1;
+                    break;
+                }
+                Some('[') => {
+                    // Skip the whole sub-branch
+                    let mut depth = 1;
+                    i += 1;
+                    while depth > 0 {
+                        match symbols.get(i) {
+                            Some('[') => depth += 1,
+                            Some(']') => depth -= 1,
+                            Some(_) => {}
+                            None => return false,
+                        }
+                        i += 1;
+                    }
+                }
+                Some(&c) if ignore.contains(c) => i += 1,
+                // Either a mismatch or the end of the current branch
+                Some(_) => return false,
+            }
+        }
+    }
+    true
+}
+
+// The first rule whose predecessor and contexts match is applied,
+// so rules with context should be listed before context-free fallbacks.
+pub fn produce_context_sensitive(axiom: &str, rules: &[ContextRule<String>], ignore: &str) -> String {
+    let symbols: Vec<char> = axiom.chars().collect();
+    let mut s = String::new();
+
+    for (i, &var) in symbols.iter().enumerate() {
+        match rules.iter().find(|rule| rule.matches(&symbols, i, ignore)) {
+            Some(rule) => s.push_str(&rule.successor),
+            None => s.push(var),
+        }
+    }
+    s
+}
+
+pub fn produce_context_sensitive_stochastic(
+    axiom: &str,
+    rules: &[ContextRule<StochasticRules>],
+    ignore: &str,
+) -> String {
+    produce_context_sensitive_stochastic_with_rng(axiom, rules, ignore, &mut rand::thread_rng())
+}
+
+pub fn produce_context_sensitive_stochastic_with_rng<R: Rng + ?Sized>(
+    axiom: &str,
+    rules: &[ContextRule<StochasticRules>],
+    ignore: &str,
+    rng: &mut R,
+) -> String {
+    let symbols: Vec<char> = axiom.chars().collect();
+    let mut s = String::new();
+
+    for (i, &var) in symbols.iter().enumerate() {
+        match rules.iter().find(|rule| rule.matches(&symbols, i, ignore)) {
+            Some(rule) => match rule.successor.choose(var.to_string().as_str(), rng) {
+                Some(string) => s.push_str(string),
+                None => s.push(var),
+            },
+            None => s.push(var),
+        }
+    }
     s
 }
 
+#[derive(Default)]
 pub struct LSystem {
+    // Named modules used in the axiom and rules, e.g. `Leaf`
+    pub alphabet: Alphabet,
     pub axiom: String,
     pub production_rules: HashMap<String, String>,
+    // Rules applied to the modules of `parse_modules(&axiom)`, see `produce_parametric`
+    pub parametric_rules: Vec<ParametricRule>,
+    // Take precedence over `production_rules` for the same symbol
+    pub stochastic_rules: StochasticRules,
+    // Named rule tables of a table L-system, e.g. vegetative and flowering growth.
+    // Symbols without a rule in the active table fall back to `production_rules`.
+    pub tables: BTreeMap<String, HashMap<String, String>>,
+    // Tables `derive` applies in each step, e.g. vegetative growth before flowering
+    pub schedule: Schedule,
+    // Applied to every derived word right after each production step
+    pub decomposition: DepthLimitedRules,
+    // Only applied when the derived word is interpreted, see `interpret`
+    pub homomorphism: DepthLimitedRules,
+    // Seed for the stochastic rules, see `derive`
+    pub seed: u64,
     pub angle: f32,
+    // Colours the grammar draws itself with, see `Turtle::palette`
+    pub palette: Option<Palette>,
+    // Written for the former meaning of `&`, see `TurtleInterpreter::abop_legacy`
+    pub legacy_ampersand: bool,
+    // Number of generations the system is meant to be derived for
+    pub iterations: usize,
+}
+
+impl LSystem {
+    // Derives `iterations` generations from the axiom, with the tables of
+    // `schedule`. The stochastic rules draw from rngs seeded with `seed`, so the
+    // same seed always yields the same plant.
+    pub fn derive(&self, iterations: usize) -> Vec<Symbol> {
+        self.derive_with_tables(iterations, |step| self.schedule.table(step))
+    }
+
+    // Derives with the table that `schedule` picks for each step, `None` selects
+    // just `production_rules`. Unknown table names are treated like `None`.
+    pub fn derive_with_tables<'s>(
+        &self,
+        iterations: usize,
+        mut schedule: impl FnMut(usize) -> Option<&'s str>,
+    ) -> Vec<Symbol> {
+        let base_rules = self.table_rules(None);
+        let tables = self.all_table_rules();
+        let stochastic_rules = self.stochastic_symbol_rules();
+
+        let mut production = self.tokenized_axiom();
+        for generation in 0..iterations {
+            let rules = schedule(generation)
+                .and_then(|name| tables.get(name))
+                .unwrap_or(&base_rules);
+            let mut next = Vec::with_capacity(production.len());
+            for (chunk, symbols) in production.chunks(CHUNK_SIZE).enumerate() {
+                let mut rng = chunk_rng(self.seed, generation, chunk);
+                produce_with_rng(symbols, rules, &stochastic_rules, &mut rng, &mut next);
+            }
+            production = next;
+        }
+        production
+    }
+
+    fn all_table_rules(&self) -> HashMap<&str, HashMap<Symbol, Vec<Symbol>>> {
+        self.tables
+            .keys()
+            .map(|name| (name.as_str(), self.table_rules(Some(name))))
+            .collect()
+    }
+
+    // `production_rules` overridden by the rules of `table`
+    fn table_rules(&self, table: Option<&str>) -> HashMap<Symbol, Vec<Symbol>> {
+        let mut rules = self.alphabet.tokenize_rules(&self.production_rules);
+        if let Some(table) = table.and_then(|name| self.tables.get(name)) {
+            rules.extend(self.alphabet.tokenize_rules(table));
+        }
+        if !self.decomposition.is_empty() {
+            // Decomposition is folded into the rules, so every derivation applies it.
+            // Symbols that only decompose are kept by the production step first.
+            let decomposition = self.decomposition.tokenize(&self.alphabet);
+            for predecessor in decomposition.keys() {
+                rules.entry(*predecessor).or_insert_with(|| vec![*predecessor]);
+            }
+            for successor in rules.values_mut() {
+                *successor = self.decompose(successor, &decomposition);
+            }
+        }
+        rules
+    }
+
+    fn stochastic_symbol_rules(&self) -> StochasticRules<Symbol, Vec<Symbol>> {
+        let rules = self.stochastic_rules.tokenize(&self.alphabet);
+        if self.decomposition.is_empty() {
+            return rules;
+        }
+        let decomposition = self.decomposition.tokenize(&self.alphabet);
+        rules.map_successors(|successor| self.decompose(&successor, &decomposition))
+    }
+
+    // The axiom is decomposed like every later generation
+    fn tokenized_axiom(&self) -> Vec<Symbol> {
+        let axiom = self.alphabet.tokenize(&self.axiom);
+        if self.decomposition.is_empty() {
+            return axiom;
+        }
+        self.decompose(&axiom, &self.decomposition.tokenize(&self.alphabet))
+    }
+
+    fn decompose(&self, word: &[Symbol], decomposition: &HashMap<Symbol, Vec<Symbol>>) -> Vec<Symbol> {
+        let mut out = Vec::with_capacity(word.len());
+        decompose(word, decomposition, self.decomposition.max_depth, &mut out);
+        out
+    }
+
+    // Applies the homomorphism rules to a derived word while it is interpreted
+    pub fn interpret<I: IntoIterator<Item = Symbol>>(&self, word: I) -> Homomorphism<I::IntoIter> {
+        Homomorphism::new(
+            word.into_iter(),
+            self.homomorphism.tokenize(&self.alphabet),
+            self.homomorphism.max_depth,
+        )
+    }
+
+    // Same result as `derive`, with the chunks of every generation rewritten in parallel
+    #[cfg(feature = "parallel")]
+    pub fn derive_parallel(&self, iterations: usize) -> Vec<Symbol> {
+        let base_rules = self.table_rules(None);
+        let tables = self.all_table_rules();
+        let stochastic_rules = self.stochastic_symbol_rules();
+
+        let mut production = self.tokenized_axiom();
+        for generation in 0..iterations {
+            let rules = self.schedule.table(generation)
+                .and_then(|name| tables.get(name))
+                .unwrap_or(&base_rules);
+            let chunks: Vec<Vec<Symbol>> = production
+                .par_chunks(CHUNK_SIZE)
+                .enumerate()
+                .map(|(chunk, symbols)| {
+                    let mut next = Vec::with_capacity(symbols.len());
+                    let mut rng = chunk_rng(self.seed, generation, chunk);
+                    produce_with_rng(symbols, rules, &stochastic_rules, &mut rng, &mut next);
+                    next
+                })
+                .collect();
+            production = chunks.concat();
+        }
+        production
+    }
+
+    // Compiles the deterministic rules and encodes the axiom for `CompiledRules::derive`.
+    // Stochastic rules are not part of the compiled form.
+    pub fn compile(&self) -> (CompiledRules, Vec<u16>) {
+        self.compile_table(None)
+    }
+
+    // Like `compile`, with the rules of `table` applied in every step
+    pub fn compile_table(&self, table: Option<&str>) -> (CompiledRules, Vec<u16>) {
+        let mut compiled = CompiledRules::new(&self.table_rules(table));
+        let axiom = compiled.encode(&self.tokenized_axiom());
+        (compiled, axiom)
+    }
+
+    // Derives the parametric rules, with the axiom read by `parse_modules`
+    // Decomposition applies after every step, to the axiom as well
+    pub fn derive_parametric(&self, iterations: usize) -> Result<Vec<Module>, ParseError> {
+        let decomposition = self.module_rules(&self.decomposition)?;
+        let decompose = |word: Vec<Module>| {
+            if decomposition.is_empty() {
+                return word;
+            }
+            let mut out = Vec::with_capacity(word.len());
+            decompose_modules(&word, &decomposition, self.decomposition.max_depth, &mut out);
+            out
+        };
+        let mut production = decompose(parse_modules(&self.axiom, &self.alphabet)?);
+        for _ in 0..iterations {
+            production = decompose(produce_parametric(&production, &self.parametric_rules));
+        }
+        Ok(production)
+    }
+
+    // Applies the homomorphism rules to a word derived by `derive_parametric`
+    pub fn interpret_parametric(&self, word: &[Module]) -> Result<Vec<Module>, ParseError> {
+        let homomorphism = self.module_rules(&self.homomorphism)?;
+        let mut out = Vec::with_capacity(word.len());
+        decompose_modules(word, &homomorphism, self.homomorphism.max_depth, &mut out);
+        Ok(out)
+    }
+
+    // Plain rules rewrite modules without parameters
+    fn module_rules(&self, rules: &DepthLimitedRules) -> Result<Vec<ParametricRule>, ParseError> {
+        rules
+            .rules
+            .iter()
+            .map(|(predecessor, successor)| {
+                ParametricRule::parse(&format!("{} -> {}", predecessor, successor), &self.alphabet)
+            })
+            .collect()
+    }
+}
+
+// Words are rewritten in chunks of this many symbols. Every chunk draws from its
+// own rng stream, so stochastic results don't depend on whether the chunks are
+// rewritten one after another or in parallel, nor on the number of threads.
+pub const CHUNK_SIZE: usize = 1 << 14;
+
+fn chunk_rng(seed: u64, generation: usize, chunk: usize) -> ChaCha8Rng {
+    let mut rng = seeded_rng(seed);
+    rng.set_stream(((generation as u64) << 32) | chunk as u64);
+    rng
+}
+
+// Stochastic rules take precedence over deterministic ones for the same symbol
+fn produce_with_rng<R: Rng + ?Sized>(
+    axiom: &[Symbol],
+    rules: &HashMap<Symbol, Vec<Symbol>>,
+    stochastic_rules: &StochasticRules<Symbol, Vec<Symbol>>,
+    rng: &mut R,
+    out: &mut Vec<Symbol>,
+) {
+    for symbol in axiom {
+        if let Some(successor) = stochastic_rules.choose(symbol, rng) {
+            out.extend_from_slice(successor);
+        } else if let Some(successor) = rules.get(symbol) {
+            out.extend_from_slice(successor);
+        } else {
+            out.push(*symbol);
+        }
+    }
+}
+
+// Same result as `produce`, with chunks of the axiom rewritten in parallel
+#[cfg(feature = "parallel")]
+pub fn produce_parallel(axiom: &str, rules: &HashMap<String, String>) -> String {
+    // Chunks have to start on char boundaries
+    let mut bounds: Vec<usize> = axiom.char_indices().step_by(CHUNK_SIZE).map(|(i, _)| i).collect();
+    bounds.push(axiom.len());
+
+    let chunks: Vec<String> = bounds
+        .par_windows(2)
+        .map(|bound| produce(&axiom[bound[0]..bound[1]], rules))
+        .collect();
+    chunks.concat()
+}
+
+// Names accepted by `preset`
+pub const PRESETS: &[&str] = &[
+    "fractal_plant",
+    "binary_tree",
+    "square",
+    "hilbert",
+    "pentaplexity",
+    "hexagonal_gosper",
+    "tree_3d",
+    "stochastic_plant",
+    "flowering_plant",
+    "row_of_trees",
+];
+
+// Looks up a preset by the name of its function, e.g. `hilbert`. Open
+// L-systems like `topiary` are left out, they need an environment to grow.
+pub fn preset(name: &str) -> Option<LSystem> {
+    match name {
+        "fractal_plant" => Some(fractal_plant()),
+        "binary_tree" => Some(binary_tree()),
+        "square" => Some(square()),
+        "hilbert" => Some(hilbert()),
+        "pentaplexity" => Some(pentaplexity()),
+        "hexagonal_gosper" => Some(hexagonal_gosper()),
+        "tree_3d" => Some(tree_3d()),
+        "stochastic_plant" => Some(stochastic_plant()),
+        "flowering_plant" => Some(flowering_plant()),
+        "row_of_trees" => Some(row_of_trees()),
+        _ => None,
+    }
 }
 
 pub fn fractal_plant() -> LSystem {
@@ -66,9 +516,12 @@ pub fn fractal_plant() -> LSystem {
         axiom: String::from("X"),
         production_rules,
         angle: 25.0,
+        iterations: 6,
+        ..LSystem::default()
     }
 }
 
+// Fractal binary tree, leaves 0 and wood 1 are both drawn as lines
 pub fn fractal_tree() -> LSystem {
     let mut production_rules = HashMap::new();
     production_rules.insert(String::from("0"), String::from("1[0]0"));
@@ -77,6 +530,26 @@ pub fn fractal_tree() -> LSystem {
         axiom: String::from("0"),
         production_rules,
         angle: 25.0,
+        iterations: 7,
+        ..LSystem::default()
+    }
+}
+
+// `fractal_tree` with turtle commands, so it can be drawn like the other presets
+pub fn binary_tree() -> LSystem {
+    let mut production_rules = HashMap::new();
+    production_rules.insert(String::from("0"), String::from("1[+0]-0"));
+    production_rules.insert(String::from("1"), String::from("11"));
+    let mut homomorphism = DepthLimitedRules::default();
+    homomorphism.rules.insert(String::from("0"), String::from("F"));
+    homomorphism.rules.insert(String::from("1"), String::from("F"));
+    LSystem {
+        axiom: String::from("0"),
+        production_rules,
+        homomorphism,
+        angle: 45.0,
+        iterations: 7,
+        ..LSystem::default()
     }
 }
 
@@ -88,6 +561,8 @@ pub fn square() -> LSystem {
         axiom: String::from("F+XF+F+XF"),
         production_rules,
         angle: 90.0,
+        iterations: 3,
+        ..LSystem::default()
     }
 }
 
@@ -100,6 +575,8 @@ pub fn hilbert() -> LSystem {
         axiom: String::from("X"),
         production_rules,
         angle: 90.0,
+        iterations: 5,
+        ..LSystem::default()
     }
 }
 
@@ -111,6 +588,8 @@ pub fn pentaplexity() -> LSystem {
         axiom: String::from("F++F++F++F++F"),
         production_rules,
         angle: 36.0,
+        iterations: 3,
+        ..LSystem::default()
     }
 }
 
@@ -123,6 +602,8 @@ pub fn hexagonal_gosper() -> LSystem {
         axiom: String::from("XF"),
         production_rules,
         angle: 60.0,
+        iterations: 4,
+        ..LSystem::default()
     }
 }
 
@@ -134,6 +615,241 @@ pub fn tree_3d() -> LSystem {
         axiom: String::from("FFFA"),
         production_rules,
         angle: 60.0,
+        iterations: 4,
+        ..LSystem::default()
+    }
+}
+
+
+// Apices d grow branches a that turn right with b or left with c
+pub fn stochastic_plant() -> LSystem {
+    let mut stochastic_rules = HashMap::new();
+    stochastic_rules.insert(String::from("d"), vec![
+        (1.0, String::from("d[cad]a")),
+        (1.0, String::from("d[bad]a")),
+    ]);
+    let mut homomorphism = DepthLimitedRules::default();
+    homomorphism.rules.insert(String::from("a"), String::from("F"));
+    homomorphism.rules.insert(String::from("b"), String::from("-"));
+    homomorphism.rules.insert(String::from("c"), String::from("+"));
+
+    LSystem {
+        axiom: String::from("d"),
+        stochastic_rules: StochasticRules::from_weights(stochastic_rules).expect("invalid weights"),
+        homomorphism,
+        angle: 25.0,
+        iterations: 7,
+        ..LSystem::default()
+    }
+}
+
+// Table L-system, grows branches in the vegetative table and puts
+// flowers on every apex once the flowering table is applied
+pub fn flowering_plant() -> LSystem {
+    let mut production_rules = HashMap::new();
+    production_rules.insert(String::from("F"), String::from("FF"));
+
+    let mut vegetative = HashMap::new();
+    vegetative.insert(String::from("A"), String::from("F[+A][-A]FA"));
+    let mut flowering = HashMap::new();
+    flowering.insert(String::from("A"), String::from("F[+F@][-F@]F@"));
+
+    let mut tables = BTreeMap::new();
+    tables.insert(String::from("vegetative"), vegetative);
+    tables.insert(String::from("flowering"), flowering);
+
+    LSystem {
+        axiom: String::from("A"),
+        production_rules,
+        tables,
+        schedule: Schedule::default().then("vegetative", 4).then("flowering", 1),
+        angle: 30.0,
+        iterations: 5,
+        ..LSystem::default()
+    }
+}
+
+// Picks a rule table for every derivation step of a table L-system,
+// e.g. five vegetative steps followed by a flowering one:
+//     let schedule = Schedule::default().then("vegetative", 5).then("flowering", 1);
+//     l_system.derive_with_tables(6, |step| schedule.table(step));
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schedule {
+    phases: Vec<(String, usize)>,
+}
+
+impl Schedule {
+    pub fn is_empty(&self) -> bool {
+        self.phases.is_empty()
+    }
+
+    // Tables in the order they are applied
+    pub fn tables(&self) -> impl Iterator<Item = &str> {
+        self.phases.iter().map(|(table, _)| table.as_str())
+    }
+
+    pub fn then(mut self, table: &str, steps: usize) -> Schedule {
+        self.phases.push((String::from(table), steps));
+        self
+    }
+
+    // The last table stays active once the schedule has run out
+    pub fn table(&self, step: usize) -> Option<&str> {
+        let mut end = 0;
+        for (table, steps) in self.phases.iter() {
+            end += steps;
+            if step < end {
+                return Some(table);
+            }
+        }
+        self.phases.last().map(|(table, _)| table.as_str())
     }
 }
 
+// Row of trees, ABOP figure 1.37
+pub fn row_of_trees() -> LSystem {
+    let rules = [
+        "F(x,t) : t==0 -> F(x*0.3,2)+F(x*0.458,1)--F(x*0.458,1)+F(x*0.7,0)",
+        "F(x,t) : t>0 -> F(x,t-1)",
+    ];
+
+    LSystem {
+        axiom: String::from("F(1,0)"),
+        parametric_rules: rules
+            .iter()
+            .map(|rule| ParametricRule::parse(rule, &Alphabet::default()).expect("invalid parametric rule"))
+            .collect(),
+        angle: 86.0,
+        iterations: 6,
+        ..LSystem::default()
+    }
+}
+
+// Synthetic topiary: the buds `?E` keep branching as long as the environment
+// reports them inside the hedge, see `query_environment`
+pub fn topiary() -> LSystem {
+    let mut alphabet = Alphabet::default();
+    alphabet.define("?E");
+    let rule = ParametricRule::parse("?E(x) : x>0 -> F[+F?E(1)][-F?E(1)]", &alphabet)
+        .expect("invalid parametric rule");
+
+    LSystem {
+        alphabet,
+        axiom: String::from("FFFFFF?E(1)"),
+        parametric_rules: vec![rule],
+        angle: 30.0,
+        iterations: 10,
+        ..LSystem::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_pick_a_table_for_every_step() {
+        let schedule = Schedule::default().then("vegetative", 2).then("flowering", 1);
+        let tables: Vec<Option<&str>> = (0..4).map(|step| schedule.table(step)).collect();
+        assert_eq!(tables, [Some("vegetative"), Some("vegetative"), Some("flowering"), Some("flowering")]);
+        assert_eq!(Schedule::default().table(0), None);
+    }
+
+    #[test]
+    fn derive_follows_the_schedule() {
+        let flowers = |word: &[Symbol]| word.iter().filter(|&&symbol| symbol == Symbol::from('@')).count();
+        let l_system = flowering_plant();
+        // Four vegetative steps branch into 81 apices, the flowering step puts three flowers on each
+        assert_eq!(flowers(&l_system.derive(4)), 0);
+        assert_eq!(flowers(&l_system.derive(5)), 243);
+    }
+
+    #[test]
+    fn stochastic_context_rules_normalise_weights() {
+        // Weights summing to less than 1.0 used to drop the symbol now and then
+        let rules = vec![
+            ContextRule::stochastic("A", 'B', "", vec![(0.2, String::from("X")), (0.2, String::from("Y"))])
+                .unwrap(),
+        ];
+        let mut rng = seeded_rng(7);
+        for _ in 0..100 {
+            let word = produce_context_sensitive_stochastic_with_rng("AB", &rules, "", &mut rng);
+            assert!(word == "AX" || word == "AY", "unexpected {}", word);
+        }
+    }
+
+    #[test]
+    fn stochastic_context_rules_reject_invalid_weights() {
+        let error = ContextRule::stochastic("", 'B', "", vec![(-1.0, String::from("X"))]).err();
+        assert!(matches!(error, Some(StochasticRuleError::InvalidWeight { .. })));
+        assert!(ContextRule::stochastic("", 'B', "", Vec::new()).is_err());
+    }
+
+    fn context_rule(left: &str, predecessor: char, right: &str, successor: &str) -> ContextRule<String> {
+        ContextRule::new(left, predecessor, right, String::from(successor))
+    }
+
+    #[test]
+    fn left_context_propagates_a_signal() {
+        let rules = [context_rule("b", 'a', "", "b")];
+        let mut word = String::from("baaa");
+        for expected in ["bbaa", "bbba", "bbbb"].iter() {
+            word = produce_context_sensitive(&word, &rules, "");
+            assert_eq!(&word, expected);
+        }
+    }
+
+    #[test]
+    fn contexts_skip_branches_they_dont_mention() {
+        // ABOP figure 1.23: S sees BC on its left and G[H]M on its right
+        let axiom = "ABC[DE][SG[HI[JK]L]MNO]";
+        let matching = [context_rule("BC", 'S', "G[H]M", "X")];
+        assert_eq!(produce_context_sensitive(axiom, &matching, ""), "ABC[DE][XG[HI[JK]L]MNO]");
+
+        // DE is a sibling branch, not an ancestor of S
+        let sibling = [context_rule("DE", 'S', "", "X")];
+        assert_eq!(produce_context_sensitive(axiom, &sibling, ""), axiom);
+        // M is only reached by skipping the branch starting with H
+        let skipped = [context_rule("", 'G', "M", "X")];
+        assert_eq!(produce_context_sensitive(axiom, &skipped, ""), "ABC[DE][SX[HI[JK]L]MNO]");
+    }
+
+    #[test]
+    fn contexts_skip_ignored_symbols() {
+        let rules = [context_rule("a", 'b', "c", "X")];
+        assert_eq!(produce_context_sensitive("a+b-Fc", &rules, ""), "a+b-Fc");
+        assert_eq!(produce_context_sensitive("a+b-Fc", &rules, "+-F"), "a+X-Fc");
+    }
+
+    // Big enough to be rewritten in several chunks, see `CHUNK_SIZE`
+    fn branching_stochastic_plant() -> LSystem {
+        parse_grammar("\
+axiom: X
+seed: 7
+X -(1)-> F[+X]F[-X]+X
+X -(2)-> F[+X]F[-X]X
+F -> FF
+").unwrap()
+    }
+
+    #[test]
+    fn seeds_reproduce_the_same_plant() {
+        let l_system = branching_stochastic_plant();
+        let plant = l_system.derive(8);
+        assert!(plant.len() > 2 * CHUNK_SIZE);
+        assert_eq!(plant, l_system.derive(8));
+
+        let other = LSystem { seed: 8, ..branching_stochastic_plant() };
+        assert_ne!(plant, other.derive(8));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_derivation_matches_sequential() {
+        let l_system = branching_stochastic_plant();
+        assert_eq!(l_system.derive_parallel(8), l_system.derive(8));
+
+        let flowering = flowering_plant();
+        assert_eq!(flowering.derive_parallel(5), flowering.derive(5));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use super::symbol::Symbol;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+// Deterministic rules compiled for fast, deep expansion. Every symbol that
+// occurs gets a dense `u16` index and all successors are stored back to back
+// in one buffer, so rewriting a symbol is a table lookup and a slice copy.
+pub struct CompiledRules {
+    // Dense index -> symbol
+    symbols: Vec<Symbol>,
+    indices: HashMap<Symbol, u16>,
+    successors: Vec<u16>,
+    // Range into `successors` per dense index, `None` for constants
+    ranges: Vec<Option<(u32, u32)>>,
+    // Number of symbols each dense index is rewritten into
+    lengths: Vec<u32>,
+}
+
+impl CompiledRules {
+    pub fn new(rules: &HashMap<Symbol, Vec<Symbol>>) -> CompiledRules {
+        let mut compiled = CompiledRules {
+            symbols: Vec::new(),
+            indices: HashMap::new(),
+            successors: Vec::new(),
+            ranges: Vec::new(),
+            lengths: Vec::new(),
+        };
+
+        for (predecessor, successor) in rules {
+            let index = compiled.intern(*predecessor);
+            let start = compiled.successors.len() as u32;
+            for symbol in successor {
+                let successor_index = compiled.intern(*symbol);
+                compiled.successors.push(successor_index);
+            }
+            let end = compiled.successors.len() as u32;
+            compiled.ranges[index as usize] = Some((start, end));
+            compiled.lengths[index as usize] = end - start;
+        }
+        compiled
+    }
+
+    fn intern(&mut self, symbol: Symbol) -> u16 {
+        if let Some(&index) = self.indices.get(&symbol) {
+            return index;
+        }
+        assert!(self.symbols.len() <= u16::MAX as usize, "more than 65536 distinct symbols");
+        let index = self.symbols.len() as u16;
+        self.symbols.push(symbol);
+        self.indices.insert(symbol, index);
+        // Symbols are constants until a rule says otherwise
+        self.ranges.push(None);
+        self.lengths.push(1);
+        index
+    }
+
+    // Symbols without a rule are added to the dense alphabet as constants
+    pub fn encode(&mut self, word: &[Symbol]) -> Vec<u16> {
+        word.iter().map(|&symbol| self.intern(symbol)).collect()
+    }
+
+    pub fn decode(&self, buffer: &[u16]) -> Vec<Symbol> {
+        buffer.iter().map(|&index| self.symbol(index)).collect()
+    }
+
+    pub fn symbol(&self, index: u16) -> Symbol {
+        self.symbols[index as usize]
+    }
+
+    // `None` for constants
+    pub fn successor(&self, index: u16) -> Option<&[u16]> {
+        self.ranges[index as usize]
+            .map(|(start, end)| &self.successors[start as usize..end as usize])
+    }
+
+    // Length of `buffer` after one production step
+    fn produced_len(&self, buffer: &[u16]) -> usize {
+        buffer.iter().map(|&index| self.lengths[index as usize] as usize).sum()
+    }
+
+    pub fn produce(&self, buffer: &[u16]) -> Vec<u16> {
+        // Knowing the exact length up front avoids regrowing the output
+        let mut out = Vec::with_capacity(self.produced_len(buffer));
+        self.produce_into(buffer, &mut out);
+        out
+    }
+
+    pub fn produce_into(&self, buffer: &[u16], out: &mut Vec<u16>) {
+        for &index in buffer {
+            match self.ranges[index as usize] {
+                Some((start, end)) => out.extend_from_slice(&self.successors[start as usize..end as usize]),
+                None => out.push(index),
+            }
+        }
+    }
+
+    pub fn derive(&self, axiom: &[u16], iterations: usize) -> Vec<u16> {
+        let mut production = axiom.to_vec();
+        for _ in 0..iterations {
+            production = self.produce(&production);
+        }
+        production
+    }
+
+    // Same result as `produce`, with chunks of the buffer rewritten in parallel
+    #[cfg(feature = "parallel")]
+    pub fn produce_parallel(&self, buffer: &[u16]) -> Vec<u16> {
+        let chunks: Vec<&[u16]> = buffer.chunks(super::CHUNK_SIZE).collect();
+        let lengths: Vec<usize> = chunks.par_iter().map(|chunk| self.produced_len(chunk)).collect();
+
+        // Every chunk writes straight into its own part of the output
+        let mut out = vec![0; lengths.iter().sum()];
+        let mut parts = Vec::with_capacity(chunks.len());
+        let mut rest = out.as_mut_slice();
+        for len in lengths {
+            let (part, tail) = rest.split_at_mut(len);
+            parts.push(part);
+            rest = tail;
+        }
+
+        chunks.into_par_iter().zip(parts).for_each(|(chunk, part)| {
+            let mut position = 0;
+            for &index in chunk {
+                match self.successor(index) {
+                    Some(successor) => {
+                        part[position..position + successor.len()].copy_from_slice(successor);
+                        position += successor.len();
+                    }
+                    None => {
+                        part[position] = index;
+                        position += 1;
+                    }
+                }
+            }
+        });
+        out
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn derive_parallel(&self, axiom: &[u16], iterations: usize) -> Vec<u16> {
+        let mut production = axiom.to_vec();
+        for _ in 0..iterations {
+            production = self.produce_parallel(&production);
+        }
+        production
+    }
+
+    // Lazily yields the symbols of generation `iterations`, see `Derivation`
+    pub fn derivation<'a>(&'a self, axiom: &'a [u16], iterations: usize) -> Derivation<'a> {
+        Derivation {
+            rules: self,
+            stack: vec![axiom],
+            iterations,
+        }
+    }
+}
+
+// Walks the derivation tree depth-first and yields one symbol of the final
+// generation at a time. Memory is bounded by the number of iterations
+// instead of the length of the derived word.
+pub struct Derivation<'a> {
+    rules: &'a CompiledRules,
+    // The remaining symbols per generation, the axiom is at the bottom
+    stack: Vec<&'a [u16]>,
+    iterations: usize,
+}
+
+impl<'a> Iterator for Derivation<'a> {
+    type Item = Symbol;
+
+    fn next(&mut self) -> Option<Symbol> {
+        let rules = self.rules;
+        loop {
+            let generation = self.stack.len().checked_sub(1)?;
+            let remaining = &mut self.stack[generation];
+            let (&index, rest) = match remaining.split_first() {
+                Some(split) => split,
+                None => {
+                    // Done with this successor, continue with its parent generation
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            *remaining = rest;
+
+            if generation == self.iterations {
+                return Some(rules.symbol(index));
+            }
+            match rules.successor(index) {
+                Some(successor) => self.stack.push(successor),
+                // Constants stay the same in every generation
+                None => return Some(rules.symbol(index)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::l_system::{LSystem, fractal_plant, fractal_tree, hilbert, tree_3d};
+
+    #[test]
+    fn derivation_matches_derive() {
+        let l_systems: [(&str, LSystem); 4] = [
+            ("fractal_plant", fractal_plant()),
+            ("hilbert", hilbert()),
+            ("tree_3d", tree_3d()),
+            ("fractal_tree", fractal_tree()),
+        ];
+        for (name, l_system) in l_systems.iter() {
+            let (rules, axiom) = l_system.compile();
+            for iterations in 0..=4 {
+                let lazy: Vec<_> = rules.derivation(&axiom, iterations).collect();
+                assert_eq!(lazy, l_system.derive(iterations), "{} at {} iterations", name, iterations);
+                assert_eq!(rules.decode(&rules.derive(&axiom, iterations)), lazy, "{}", name);
+            }
+        }
+    }
+}
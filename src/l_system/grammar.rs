@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use super::{Alphabet, LSystem, Palette, ParametricRule, Schedule, StochasticRules, parse_modules};
+
+// Plain text grammar format:
+//
+//     # Fractal plant
+//     axiom: X
+//     angle: 25
+//     iterations: 6
+//     seed: 42
+//     modules: Leaf Apex
+//
+//     X -> F+[[X]-X]-F[-FX]+X
+//     F -> FF
+//
+//     # Stochastic rules carry a relative weight on the arrow
+//     d -(1)-> d[cad]a
+//     d -(3)-> d[bad]a
+//
+//     # Parametric rules, see `ParametricRule::parse`. Next to them plain
+//     # rules, decomposition and homomorphism included, match modules without
+//     # parameters. Stochastic rules and tables can't be used with them
+//     A(t) : t>0 -> F(t)A(t-1)
+//
+//     # Deterministic rules after a `table:` line belong to that table,
+//     # `schedule:` lists the tables to apply and for how many steps
+//     schedule: vegetative 4 flowering 1
+//     table: flowering
+//     A -> F[+F@][-F@]F@
+//
+//     # Rules after `decomposition:` or `homomorphism:` belong there,
+//     # the optional value is the maximum recursion depth
+//     homomorphism: 2
+//     Leaf -> [+F-F-F]
+//
+//     # Colours, see `Palette`. `;(n)` picks colour n, which takes a
+//     # parametric system, `'` and `;` step to the next colour anywhere
+//     palette: depth 6 8b4513 228b22
+//
+//     # `&` swaps `+` and `-` instead of pitching down, like it used to
+//     legacy_ampersand: true
+//
+// Named modules are declared with `modules:`, anywhere in the file. Symbols
+// are matched longest name first, so `Apex` is never read as `A`, `p`, `e`, `x`.
+// Whitespace separates symbols, so with a module `AB` the axiom `A B` is still
+// the two symbols `A` and `B`.
+//
+// `#` starts a comment at the beginning of a line or after whitespace,
+// so it can still be used as a turtle command inside successors.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrammarError {
+    // Both 1-based
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl GrammarError {
+    // `offset` is a byte offset into `text`
+    fn new(line: usize, text: &str, offset: usize, message: impl Into<String>) -> GrammarError {
+        GrammarError {
+            line,
+            column: text[..offset].chars().count() + 1,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+// Where the deterministic rules of the following lines go
+enum Section {
+    Production,
+    Table(String),
+    Decomposition,
+    Homomorphism,
+}
+
+impl Section {
+    fn name(&self) -> &str {
+        match self {
+            Section::Production => "production",
+            Section::Table(_) => "table",
+            Section::Decomposition => "decomposition",
+            Section::Homomorphism => "homomorphism",
+        }
+    }
+}
+
+pub fn load_grammar(path: &Path) -> Result<LSystem, Box<dyn std::error::Error>> {
+    let source = std::fs::read_to_string(path)?;
+    Ok(parse_grammar(&source)?)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..i];
+        }
+        previous = c;
+    }
+    line
+}
+
+// Byte offset of the first non-whitespace character of `part` within `line`
+fn offset_in(line: &str, part: &str) -> usize {
+    let start = part.as_ptr() as usize - line.as_ptr() as usize;
+    start + part.len() - part.trim_start().len()
+}
+
+// Byte offset within `line` of a `;(n)` in `part`, which is a module with a
+// parameter and only exists in parametric systems. `(` is a turtle command
+// too, so `;(F)` is just `;` followed by `(`, `F` and `)`.
+fn colour_parameter(line: &str, part: &str) -> Option<usize> {
+    let start = part.as_ptr() as usize - line.as_ptr() as usize;
+    part.match_indices(";(")
+        .find(|&(i, _)| {
+            let module = match find_close(&part[i + 1..]) {
+                Some(close) => &part[i..i + 2 + close],
+                None => return false,
+            };
+            matches!(parse_modules(module, &Alphabet::default()), Ok(modules) if !modules[0].params.is_empty())
+        })
+        .map(|(i, _)| start + i)
+}
+
+// Byte offset of the `)` closing the `(` that `source` starts with
+fn find_close(source: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits `lhs -(weight)-> rhs` or `lhs -> rhs`, returning the weight if present
+fn split_arrow(line: &str) -> Option<(&str, Option<&str>, &str)> {
+    let arrow = line.find("->")?;
+    if let Some(close) = line[..arrow].strip_suffix(')') {
+        if let Some(open) = close.rfind("-(") {
+            if !close[..open].is_empty() && !close[open + 2..].contains('(') {
+                return Some((&line[..open], Some(&close[open + 2..]), &line[arrow + 2..]));
+            }
+        }
+    }
+    Some((&line[..arrow], None, &line[arrow + 2..]))
+}
+
+pub fn parse_grammar(source: &str) -> Result<LSystem, GrammarError> {
+    let mut l_system = LSystem::default();
+    let mut axiom = None;
+    let mut weighted_rules: HashMap<String, Vec<(f32, String)>> = HashMap::new();
+    // Where each stochastic predecessor was first seen, for error reporting
+    let mut stochastic_lines: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut section = Section::Production;
+    // Where the schedule was given, its tables are checked once all are known
+    let mut schedule_line = None;
+    // The first `;(n)` outside a parametric rule
+    let mut colour_parameter_at = None;
+    // Lines of the plain rules, with whether they are productions, and of the
+    // first table, which grammars with parametric rules check or reject
+    let mut plain_rule_lines = Vec::new();
+    let mut table_line = None;
+
+    // Named modules have to be known before any rule is tokenized
+    for line in source.lines().map(strip_comment) {
+        if let Some(("modules", names)) = line.split_once(':').map(|(key, names)| (key.trim(), names)) {
+            if !line.contains("->") {
+                for name in names.split_whitespace() {
+                    l_system.alphabet.define(name);
+                }
+            }
+        }
+    }
+
+    for (index, raw_line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(raw_line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |part: &str, message: String| {
+            GrammarError::new(line_number, raw_line, offset_in(raw_line, part), message)
+        };
+
+        match split_arrow(line) {
+            Some((lhs, weight, rhs)) => {
+                let predecessor = lhs.trim();
+                let deterministic_only = !matches!(section, Section::Production);
+                if deterministic_only && (weight.is_some() || predecessor.contains('(') || predecessor.contains(':')) {
+                    return Err(error(lhs, format!(
+                        "only deterministic rules are allowed after '{}:'", section.name()
+                    )));
+                }
+                if predecessor.contains('(') || predecessor.contains(':') {
+                    if weight.is_some() {
+                        return Err(error(lhs, String::from("parametric rules cannot be stochastic")));
+                    }
+                    let rule = ParametricRule::parse(line, &l_system.alphabet).map_err(|e| {
+                        GrammarError::new(line_number, raw_line, e.position, e.message)
+                    })?;
+                    l_system.parametric_rules.push(rule);
+                    continue;
+                }
+
+                if l_system.alphabet.single_symbol(predecessor).is_none() {
+                    return Err(error(lhs, format!(
+                        "predecessor '{}' has to be a single symbol", predecessor
+                    )));
+                }
+                // Whitespace is kept, it separates named modules from their neighbours
+                let successor = rhs.trim().to_string();
+                if colour_parameter_at.is_none() {
+                    colour_parameter_at = colour_parameter(raw_line, rhs).map(|offset| (line_number, offset));
+                }
+
+                match weight {
+                    Some(weight_source) => {
+                        let weight = weight_source.trim().parse::<f32>().map_err(|_| {
+                            error(weight_source, format!("invalid weight '{}'", weight_source.trim()))
+                        })?;
+                        if l_system.production_rules.contains_key(predecessor) {
+                            return Err(error(lhs, format!(
+                                "'{}' already has a deterministic rule", predecessor
+                            )));
+                        }
+                        stochastic_lines
+                            .entry(predecessor.to_string())
+                            .or_insert((line_number, offset_in(raw_line, lhs)));
+                        weighted_rules
+                            .entry(predecessor.to_string())
+                            .or_default()
+                            .push((weight, successor));
+                    }
+                    None => {
+                        match section {
+                            Section::Production => plain_rule_lines.push((line_number, true)),
+                            Section::Decomposition | Section::Homomorphism => plain_rule_lines.push((line_number, false)),
+                            Section::Table(_) => {}
+                        }
+                        let rules = match &section {
+                            Section::Production => &mut l_system.production_rules,
+                            Section::Table(name) => l_system.tables.entry(name.clone()).or_default(),
+                            Section::Decomposition => &mut l_system.decomposition.rules,
+                            Section::Homomorphism => &mut l_system.homomorphism.rules,
+                        };
+                        if rules.contains_key(predecessor)
+                            || (!deterministic_only && weighted_rules.contains_key(predecessor))
+                        {
+                            return Err(error(lhs, format!(
+                                "'{}' already has a rule", predecessor
+                            )));
+                        }
+                        rules.insert(predecessor.to_string(), successor);
+                    }
+                }
+            }
+            None => {
+                let colon = line.find(':').ok_or_else(|| {
+                    error(line, String::from("expected a rule 'A -> B' or a setting 'key: value'"))
+                })?;
+                let (key, value) = (&line[..colon], &line[colon + 1..]);
+                let trimmed = value.trim();
+                let invalid = || error(value, format!("invalid value '{}' for '{}'", trimmed, key.trim()));
+
+                match key.trim() {
+                    "axiom" => {
+                        axiom = Some(trimmed.to_string());
+                        if colour_parameter_at.is_none() {
+                            colour_parameter_at = colour_parameter(raw_line, value).map(|offset| (line_number, offset));
+                        }
+                    }
+                    "angle" => l_system.angle = trimmed.parse().map_err(|_| invalid())?,
+                    "iterations" => l_system.iterations = trimmed.parse().map_err(|_| invalid())?,
+                    "seed" => l_system.seed = trimmed.parse().map_err(|_| invalid())?,
+                    "schedule" => {
+                        // Pairs of table name and number of steps
+                        let words: Vec<&str> = trimmed.split_whitespace().collect();
+                        let phases = words.chunks_exact(2);
+                        if words.is_empty() || !phases.remainder().is_empty() {
+                            return Err(invalid());
+                        }
+                        let mut schedule = Schedule::default();
+                        for phase in phases {
+                            schedule = schedule.then(phase[0], phase[1].parse().map_err(|_| invalid())?);
+                        }
+                        l_system.schedule = schedule;
+                        schedule_line = Some((line_number, offset_in(raw_line, value)));
+                    }
+                    "legacy_ampersand" => l_system.legacy_ampersand = trimmed.parse().map_err(|_| invalid())?,
+                    "palette" => l_system.palette = Some(Palette::parse(trimmed).ok_or_else(invalid)?),
+                    "modules" => {}
+                    "table" if !trimmed.is_empty() => {
+                        l_system.tables.entry(trimmed.to_string()).or_default();
+                        table_line.get_or_insert((line_number, offset_in(raw_line, key)));
+                        section = Section::Table(trimmed.to_string());
+                    }
+                    "decomposition" | "homomorphism" => {
+                        let (rules, next) = match key.trim() {
+                            "decomposition" => (&mut l_system.decomposition, Section::Decomposition),
+                            _ => (&mut l_system.homomorphism, Section::Homomorphism),
+                        };
+                        if !trimmed.is_empty() {
+                            rules.max_depth = trimmed.parse().map_err(|_| invalid())?;
+                        }
+                        section = next;
+                    }
+                    other => return Err(error(key, format!("unknown setting '{}'", other))),
+                }
+            }
+        }
+    }
+
+    l_system.axiom = axiom.ok_or(GrammarError {
+        line: 1,
+        column: 1,
+        message: String::from("missing 'axiom'"),
+    })?;
+
+    if let Some((line, offset)) = schedule_line {
+        if let Some(unknown) = l_system.schedule.tables().find(|table| !l_system.tables.contains_key(*table)) {
+            let text = source.lines().nth(line - 1).unwrap_or("");
+            return Err(GrammarError::new(line, text, offset, format!("unknown table '{}'", unknown)));
+        }
+    }
+
+    if !l_system.parametric_rules.is_empty() {
+        // Parametric rewriting only knows parametric rules
+        let first_stochastic = stochastic_lines.values().min().copied();
+        for (at, what) in [(first_stochastic, "stochastic rules"), (table_line, "rule tables")].iter() {
+            if let Some((line, offset)) = *at {
+                let text = source.lines().nth(line - 1).unwrap_or("");
+                return Err(GrammarError::new(line, text, offset, format!(
+                    "{} can't be mixed with parametric rules", what
+                )));
+            }
+        }
+        // Plain rules become parametric rules without parameters or guard, the
+        // decomposition and homomorphism are only checked here
+        for (line, production) in plain_rule_lines {
+            let text = source.lines().nth(line - 1).unwrap_or("");
+            let rule = ParametricRule::parse(strip_comment(text), &l_system.alphabet).map_err(|e| {
+                GrammarError::new(line, text, e.position, e.message)
+            })?;
+            if production {
+                l_system.parametric_rules.push(rule);
+            }
+        }
+        l_system.production_rules.clear();
+    }
+
+    if let Some((line, offset)) = colour_parameter_at.filter(|_| l_system.parametric_rules.is_empty()) {
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        return Err(GrammarError::new(line, text, offset, String::from(
+            "';(n)' needs parametric rules, use ';' for the next colour",
+        )));
+    }
+
+    l_system.stochastic_rules = StochasticRules::from_weights(weighted_rules).map_err(|e| {
+        let (line, offset) = stochastic_lines[e.predecessor()];
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        GrammarError::new(line, text, offset, e.to_string())
+    })?;
+
+    Ok(l_system)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::Symbol;
+    use crate::l_system::parametric::modules_to_string;
+
+    // Line and column of the error `source` is rejected with
+    fn error_position(source: &str) -> (usize, usize) {
+        match parse_grammar(source) {
+            Ok(_) => panic!("{:?} was accepted", source),
+            Err(error) => (error.line, error.column),
+        }
+    }
+
+    #[test]
+    fn parses_settings_and_rules() {
+        let source = "\
+# Fractal plant
+axiom: X
+angle: 25
+iterations: 6
+seed: 42
+
+X -> F+[[X]-X]-F[-FX]+X   # comment
+F -> FF
+d -(1)-> d[cad]a
+d -(3)-> d[bad]a
+table: flowering
+A -> F@
+";
+        let l_system = parse_grammar(source).unwrap();
+        assert_eq!(l_system.axiom, "X");
+        assert_eq!((l_system.angle, l_system.iterations, l_system.seed), (25.0, 6, 42));
+        assert_eq!(l_system.production_rules["X"], "F+[[X]-X]-F[-FX]+X");
+        assert_eq!(l_system.production_rules["F"], "FF");
+        assert!(!l_system.stochastic_rules.is_empty());
+        assert_eq!(l_system.tables["flowering"]["A"], "F@");
+    }
+
+    #[test]
+    fn named_modules_are_matched_longest_first() {
+        let l_system = parse_grammar("modules: Apex\naxiom: Apex\nApex -> F[+Apex]A").unwrap();
+        assert!(l_system.production_rules.contains_key("Apex"));
+        assert_eq!(error_position("modules: Apex\naxiom: A\nApe -> F"), (3, 1));
+    }
+
+    #[test]
+    fn whitespace_separates_named_modules_from_their_prefixes() {
+        let l_system = parse_grammar("modules: AB\naxiom: A B AB\nA -> B A B").unwrap();
+        let (a, b, ab) = (Symbol::from('A'), Symbol::from('B'), l_system.alphabet.symbol("AB").unwrap());
+        assert_eq!(l_system.derive(0), vec![a, b, ab]);
+        assert_eq!(l_system.derive(1), vec![b, a, b, b, ab]);
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        // Missing arrow or colon
+        assert_eq!(error_position("axiom: F\n  F FF"), (2, 3));
+        assert_eq!(error_position("axiom: F\nangle: steep"), (2, 8));
+        assert_eq!(error_position("axiom: F\nsize: 3"), (2, 1));
+        assert_eq!(error_position("axiom: F\nF -> FF\nF -> F"), (3, 1));
+        assert_eq!(error_position("axiom: F\nF -(x)-> FF"), (2, 5));
+        // Weights are checked once all rules are known, at the first rule
+        assert_eq!(error_position("axiom: F\nF -(0)-> FF\nF -(0)-> F"), (2, 1));
+        assert_eq!(error_position("axiom: A\nschedule: vegetative 2"), (2, 11));
+        assert_eq!(error_position("F -> FF"), (1, 1));
+    }
+
+    #[test]
+    fn colour_parameters_need_parametric_rules() {
+        assert_eq!(error_position("axiom: F\nF -> F[;(2)F]"), (2, 8));
+        assert_eq!(error_position("axiom: ;(1)F\nF -> FF"), (1, 8));
+
+        assert!(parse_grammar("axiom: ;(1)A(2)\nA(t) : t>0 -> F;(2)A(t-1)").is_ok());
+        assert!(parse_grammar("axiom: F\nF -> F[;F]").is_ok());
+        // `(` on its own decreases the turning angle
+        assert!(parse_grammar("axiom: F\nF -> F;(F)").is_ok());
+        assert_eq!(error_position("axiom: F\nF -> F;(F);(1+1)"), (2, 11));
+    }
+
+    #[test]
+    fn plain_rules_join_parametric_ones() {
+        let l_system = parse_grammar("axiom: A(1)\nA(t) -> F(t)B\nB -> FF").unwrap();
+        assert!(l_system.production_rules.is_empty());
+        let word = l_system.derive_parametric(2).unwrap();
+        assert_eq!(modules_to_string(&word, &l_system.alphabet), "F(1)FF");
+
+        assert_eq!(error_position("axiom: A(1)\nA(t) -> F(t)B\nB -(1)-> F"), (3, 1));
+        assert_eq!(error_position("axiom: A(1)\nA(t) -> F(t)B\ntable: grow\nB -> F"), (3, 1));
+        assert_eq!(error_position("axiom: A(1)\nA(t) -> F(t)B\nB -> F(1+)"), (3, 10));
+        assert_eq!(error_position("axiom: A(1)\nA(t) -> F(t)B\nhomomorphism:\nB -> F(t)"), (4, 8));
+    }
+
+    #[test]
+    fn legacy_ampersand_is_a_setting() {
+        assert!(parse_grammar("axiom: F\nlegacy_ampersand: true").unwrap().legacy_ampersand);
+        assert!(!parse_grammar("axiom: F").unwrap().legacy_ampersand);
+        assert_eq!(error_position("axiom: F\nlegacy_ampersand: yes"), (2, 19));
+    }
+}
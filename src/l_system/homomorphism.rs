@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use super::parametric::{Module, ParametricRule};
+use super::symbol::{Alphabet, Symbol};
+
+// Rules that describe appearance rather than growth, ABOP chapter 1.10.2.
+//
+// Decomposition rules are applied right after every production step, so a
+// module like a compound leaf turns into its parts in the same step it is
+// produced. Homomorphism rules are only applied while the word is being
+// interpreted, e.g. to expand `Leaf` into a drawing sequence. The derived
+// word stays small while the rendered geometry is detailed.
+//
+// Successors are rewritten again until no rule applies or `max_depth` is
+// reached, which guards against rules that would recurse forever.
+
+const DEFAULT_MAX_DEPTH: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct DepthLimitedRules {
+    pub rules: HashMap<String, String>,
+    pub max_depth: usize,
+}
+
+impl Default for DepthLimitedRules {
+    fn default() -> Self {
+        DepthLimitedRules {
+            rules: HashMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl DepthLimitedRules {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn tokenize(&self, alphabet: &Alphabet) -> HashMap<Symbol, Vec<Symbol>> {
+        alphabet.tokenize_rules(&self.rules)
+    }
+}
+
+// Appends `word` to `out` with every symbol decomposed recursively
+pub fn decompose(word: &[Symbol], rules: &HashMap<Symbol, Vec<Symbol>>, max_depth: usize, out: &mut Vec<Symbol>) {
+    for &symbol in word {
+        match rules.get(&symbol) {
+            Some(successor) if max_depth > 0 => decompose(successor, rules, max_depth - 1, out),
+            _ => out.push(symbol),
+        }
+    }
+}
+
+// Same as `decompose` for parametric words, the first matching rule rewrites a module
+pub fn decompose_modules(word: &[Module], rules: &[ParametricRule], max_depth: usize, out: &mut Vec<Module>) {
+    for module in word {
+        match rules.iter().find(|rule| rule.matches(module)) {
+            Some(rule) if max_depth > 0 => {
+                let mut successor = Vec::new();
+                rule.apply(module, &mut successor);
+                decompose_modules(&successor, rules, max_depth - 1, out);
+            }
+            _ => out.push(module.clone()),
+        }
+    }
+}
+
+// Lazily applies homomorphism rules to the symbols of `word`
+pub struct Homomorphism<I> {
+    word: I,
+    rules: HashMap<Symbol, Vec<Symbol>>,
+    max_depth: usize,
+    // Successors being expanded with the position of the next symbol in each
+    stack: Vec<(Symbol, usize)>,
+}
+
+impl<I: Iterator<Item = Symbol>> Homomorphism<I> {
+    pub fn new(word: I, rules: HashMap<Symbol, Vec<Symbol>>, max_depth: usize) -> Homomorphism<I> {
+        Homomorphism {
+            word,
+            rules,
+            max_depth,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Symbol>> Iterator for Homomorphism<I> {
+    type Item = Symbol;
+
+    fn next(&mut self) -> Option<Symbol> {
+        loop {
+            let symbol = match self.stack.last_mut() {
+                Some((predecessor, position)) => match self.rules[predecessor].get(*position) {
+                    Some(&symbol) => {
+                        *position += 1;
+                        symbol
+                    }
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                },
+                None => self.word.next()?,
+            };
+
+            if self.stack.len() < self.max_depth && self.rules.contains_key(&symbol) {
+                self.stack.push((symbol, 0));
+            } else {
+                return Some(symbol);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::parametric::modules_to_string;
+    use crate::l_system::parse_grammar;
+
+    fn rules(rules: &[(&str, &str)], alphabet: &Alphabet) -> HashMap<Symbol, Vec<Symbol>> {
+        let rules = rules.iter().map(|(p, s)| (p.to_string(), s.to_string())).collect();
+        alphabet.tokenize_rules(&rules)
+    }
+
+    #[test]
+    fn decompose_expands_successors_recursively() {
+        let alphabet = Alphabet::default();
+        let rules = rules(&[("A", "B[C]"), ("C", "DD")], &alphabet);
+        let mut out = Vec::new();
+        decompose(&alphabet.tokenize("FAC"), &rules, DEFAULT_MAX_DEPTH, &mut out);
+        assert_eq!(alphabet.to_string(&out), "FB[DD]DD");
+    }
+
+    #[test]
+    fn homomorphism_matches_decompose() {
+        let alphabet = Alphabet::default();
+        let rules = rules(&[("A", "B[C]"), ("C", "DD")], &alphabet);
+        let word = alphabet.tokenize("FAC");
+        let symbols: Vec<Symbol> = Homomorphism::new(word.into_iter(), rules, DEFAULT_MAX_DEPTH).collect();
+        assert_eq!(alphabet.to_string(&symbols), "FB[DD]DD");
+    }
+
+    #[test]
+    fn max_depth_limits_the_recursion() {
+        let alphabet = Alphabet::default();
+        let rules = rules(&[("A", "AB")], &alphabet);
+        let mut out = Vec::new();
+        decompose(&alphabet.tokenize("A"), &rules, 3, &mut out);
+        assert_eq!(alphabet.to_string(&out), "ABBB");
+
+        let symbols: Vec<Symbol> = Homomorphism::new(alphabet.tokenize("A").into_iter(), rules, 3).collect();
+        assert_eq!(alphabet.to_string(&symbols), "ABBB");
+    }
+
+    #[test]
+    fn self_recursive_rules_terminate() {
+        let alphabet = Alphabet::default();
+        let rules = rules(&[("A", "A")], &alphabet);
+        let mut out = Vec::new();
+        decompose(&alphabet.tokenize("AA"), &rules, DEFAULT_MAX_DEPTH, &mut out);
+        assert_eq!(alphabet.to_string(&out), "AA");
+
+        let symbols: Vec<Symbol> = Homomorphism::new(alphabet.tokenize("AA").into_iter(), rules, DEFAULT_MAX_DEPTH).collect();
+        assert_eq!(alphabet.to_string(&symbols), "AA");
+    }
+
+    #[test]
+    fn parametric_words_are_decomposed_and_interpreted() {
+        let source = "axiom: A(1)\nA(t) -> A(t+1)L\ndecomposition:\nL -> [F]\nhomomorphism:\nF -> G";
+        let l_system = parse_grammar(source).unwrap();
+        let word = l_system.derive_parametric(1).unwrap();
+        assert_eq!(modules_to_string(&word, &l_system.alphabet), "A(2)[F]");
+        let word = l_system.interpret_parametric(&word).unwrap();
+        assert_eq!(modules_to_string(&word, &l_system.alphabet), "A(2)[G]");
+    }
+}
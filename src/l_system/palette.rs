@@ -0,0 +1,80 @@
+use palette::Srgb;
+
+// Same type as nannou's `Rgb8`, which re-exports this version of palette
+pub type Rgb8 = Srgb<u8>;
+
+// Colours a grammar picks for its own drawing, applied by the turtle:
+//
+//     palette: 8b4513 228b22 ffc0cb          # chosen with ' and ;(n)
+//     palette: depth 6 8b4513 228b22         # by branch depth, up to depth 6
+//     palette: distance 300 8b4513 228b22    # by path length from the root
+//
+// Colours are hex triplets without `#`, which would start a comment.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Palette {
+    // Picked by the colour index, wrapping around at the end
+    Indexed(Vec<Rgb8>),
+    // Evenly spread from the root to branches `max_depth` deep
+    Depth { colors: Vec<Rgb8>, max_depth: usize },
+    // Evenly spread from the root to `max_distance` along the branches
+    Distance { colors: Vec<Rgb8>, max_distance: f32 },
+}
+
+impl Palette {
+    // Black for a palette without colours
+    pub fn color(&self, index: usize, depth: usize, distance: f32) -> Rgb8 {
+        match self {
+            Palette::Indexed(colors) if !colors.is_empty() => colors[index % colors.len()],
+            Palette::Depth { colors, max_depth } => {
+                gradient(colors, depth as f32 / (*max_depth).max(1) as f32)
+            }
+            Palette::Distance { colors, max_distance } => {
+                gradient(colors, distance / max_distance.max(f32::EPSILON))
+            }
+            _ => Rgb8::new(0, 0, 0),
+        }
+    }
+
+    // The value of a `palette:` setting, see above
+    pub fn parse(source: &str) -> Option<Palette> {
+        let mut words = source.split_whitespace().peekable();
+        let gradient = match words.peek() {
+            Some(&"depth") | Some(&"distance") => words.next().zip(words.next()),
+            _ => None,
+        };
+        let colors = words.map(parse_color).collect::<Option<Vec<Rgb8>>>()?;
+        if colors.is_empty() {
+            return None;
+        }
+
+        match gradient {
+            Some(("depth", max)) => Some(Palette::Depth { colors, max_depth: max.parse().ok()? }),
+            Some((_, max)) => Some(Palette::Distance { colors, max_distance: max.parse().ok()? }),
+            None => Some(Palette::Indexed(colors)),
+        }
+    }
+}
+
+// `8b4513`
+pub fn parse_color(hex: &str) -> Option<Rgb8> {
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb8::new(channel(0)?, channel(2)?, channel(4)?))
+}
+
+// Linear interpolation between neighbouring colours, `t` runs from 0.0 to 1.0
+fn gradient(colors: &[Rgb8], t: f32) -> Rgb8 {
+    let last = match colors.len() {
+        0 => return Rgb8::new(0, 0, 0),
+        n => n - 1,
+    };
+    let position = t.clamp(0.0, 1.0) * last as f32;
+    let i = (position.floor() as usize).min(last);
+    let (a, b) = (colors[i], colors[(i + 1).min(last)]);
+    let fraction = position - i as f32;
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * fraction).round() as u8;
+    Rgb8::new(mix(a.red, b.red), mix(a.green, b.green), mix(a.blue, b.blue))
+}
@@ -0,0 +1,600 @@
+use std::fmt;
+
+use super::symbol::{Alphabet, Symbol};
+
+// Parametric L-systems as described in ABOP chapter 1.10.
+//
+// A module is a symbol with a list of real valued parameters, e.g. `F(1.5,2)`
+// or `Leaf(3)` for modules named in the alphabet.
+// A rule names formal parameters on its predecessor, may carry a guard
+// condition and computes the parameters of its successor modules from
+// arithmetic expressions:
+//
+//     A(t) : t>0 -> F(t)A(t-1)
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub symbol: Symbol,
+    pub params: Vec<f32>,
+}
+
+impl Module {
+    pub fn new(symbol: Symbol, params: Vec<f32>) -> Module {
+        Module { symbol, params }
+    }
+}
+
+impl From<Symbol> for Module {
+    fn from(symbol: Symbol) -> Module {
+        Module { symbol, params: Vec::new() }
+    }
+}
+
+pub fn modules_to_string(modules: &[Module], alphabet: &Alphabet) -> String {
+    let mut s = String::new();
+    for module in modules {
+        s.push_str(&alphabet.name(module.symbol));
+        if !module.params.is_empty() {
+            let params: Vec<String> = module.params.iter().map(|p| p.to_string()).collect();
+            s.push_str(&format!("({})", params.join(",")));
+        }
+    }
+    s
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // Byte offset into the parsed source
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> ParseError {
+        ParseError { position, message: message.into() }
+    }
+
+    fn offset(mut self, offset: usize) -> ParseError {
+        self.position += offset;
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    // Index into the formal parameters of the rule
+    Param(usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+fn truth(value: bool) -> f32 {
+    if value { 1.0 } else { 0.0 }
+}
+
+impl Expr {
+    pub fn parse(source: &str, formals: &[String]) -> Result<Expr, ParseError> {
+        let mut parser = ExprParser { source, position: 0, formals };
+        let expr = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.position < source.len() {
+            return Err(ParseError::new(parser.position, "unexpected trailing input"));
+        }
+        Ok(expr)
+    }
+
+    // Booleans are represented as 1.0 (true) and 0.0 (false)
+    pub fn eval(&self, args: &[f32]) -> f32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Param(index) => args[*index],
+            Expr::Neg(expr) => -expr.eval(args),
+            Expr::Not(expr) => truth(expr.eval(args) == 0.0),
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.eval(args);
+                let b = rhs.eval(args);
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    BinaryOp::Pow => a.powf(b),
+                    BinaryOp::Lt => truth(a < b),
+                    BinaryOp::Gt => truth(a > b),
+                    BinaryOp::Le => truth(a <= b),
+                    BinaryOp::Ge => truth(a >= b),
+                    BinaryOp::Eq => truth(a == b),
+                    BinaryOp::Ne => truth(a != b),
+                    BinaryOp::And => truth(a != 0.0 && b != 0.0),
+                    BinaryOp::Or => truth(a != 0.0 || b != 0.0),
+                }
+            }
+        }
+    }
+}
+
+// Recursive descent parser, from lowest to highest precedence:
+//   or      := and ('||' and)*
+//   and     := cmp ('&&' cmp)*
+//   cmp     := sum (('<' | '>' | '<=' | '>=' | '==' | '!=') sum)?
+//   sum     := product (('+' | '-') product)*
+//   product := unary (('*' | '/') unary)*
+//   unary   := '-' unary | '!' unary | power
+//   power   := atom ('^' unary)?
+//   atom    := number | identifier | '(' or ')'
+// Negation binds looser than '^', so `-x^2` is `-(x^2)`. Numbers may have an
+// exponent, e.g. `1e-3`.
+struct ExprParser<'a> {
+    source: &'a str,
+    position: usize,
+    formals: &'a [String],
+}
+
+impl<'a> ExprParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_and()?;
+        while self.eat("||") {
+            let rhs = self.parse_and()?;
+            lhs = Self::binary(BinaryOp::Or, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_comparison()?;
+        while self.eat("&&") {
+            let rhs = self.parse_comparison()?;
+            lhs = Self::binary(BinaryOp::And, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let lhs = self.parse_sum()?;
+        // Two character operators have to be tried first
+        let operators = [
+            ("<=", BinaryOp::Le),
+            (">=", BinaryOp::Ge),
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ];
+        for (token, op) in operators.iter() {
+            if self.eat(token) {
+                let rhs = self.parse_sum()?;
+                return Ok(Self::binary(*op, lhs, rhs));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_product()?;
+        loop {
+            if self.eat("+") {
+                let rhs = self.parse_product()?;
+                lhs = Self::binary(BinaryOp::Add, lhs, rhs);
+            } else if self.eat("-") {
+                let rhs = self.parse_product()?;
+                lhs = Self::binary(BinaryOp::Sub, lhs, rhs);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            if self.eat("*") {
+                let rhs = self.parse_unary()?;
+                lhs = Self::binary(BinaryOp::Mul, lhs, rhs);
+            } else if self.eat("/") {
+                let rhs = self.parse_unary()?;
+                lhs = Self::binary(BinaryOp::Div, lhs, rhs);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn parse_power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.parse_atom()?;
+        if self.eat("^") {
+            // Right associative, the exponent may be negated like `2^-1`
+            let exponent = self.parse_unary()?;
+            return Ok(Self::binary(BinaryOp::Pow, base, exponent));
+        }
+        Ok(base)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        // Don't mistake the start of '!=' for a negation
+        self.skip_whitespace();
+        if self.rest().starts_with('!') && !self.rest().starts_with("!=") {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_power()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, ParseError> {
+        self.skip_whitespace();
+        let start = self.position;
+        let rest = self.rest();
+
+        if self.eat("(") {
+            let expr = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(ParseError::new(self.position, "expected ')'"));
+            }
+            return Ok(expr);
+        }
+
+        match rest.chars().next() {
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut len = rest
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .unwrap_or(rest.len());
+                len += exponent_len(&rest[len..]);
+                self.position += len;
+                rest[..len]
+                    .parse::<f32>()
+                    .map(Expr::Number)
+                    .map_err(|_| ParseError::new(start, format!("invalid number '{}'", &rest[..len])))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                self.position += len;
+                let name = &rest[..len];
+                match self.formals.iter().position(|formal| formal == name) {
+                    Some(index) => Ok(Expr::Param(index)),
+                    None => Err(ParseError::new(start, format!("unknown parameter '{}'", name))),
+                }
+            }
+            Some(c) => Err(ParseError::new(start, format!("unexpected '{}'", c))),
+            None => Err(ParseError::new(start, "unexpected end of expression")),
+        }
+    }
+}
+
+// Length of an exponent like `e-3` at the start of `source`, 0 if there is none
+fn exponent_len(source: &str) -> usize {
+    if !source.starts_with(['e', 'E']) {
+        return 0;
+    }
+    let sign = if source[1..].starts_with(['+', '-']) { 1 } else { 0 };
+    let digits = source[1 + sign..].find(|c: char| !c.is_ascii_digit()).unwrap_or(source.len() - 1 - sign);
+    if digits == 0 { 0 } else { 1 + sign + digits }
+}
+
+// A module in the successor of a rule, its parameters are expressions over
+// the formal parameters of the predecessor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleTemplate {
+    pub symbol: Symbol,
+    pub args: Vec<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParametricRule {
+    pub predecessor: Symbol,
+    pub formals: Vec<String>,
+    pub condition: Option<Expr>,
+    pub successor: Vec<ModuleTemplate>,
+}
+
+impl ParametricRule {
+    // Parses rules of the form `A(t) : t>0 -> F(t)A(t-1)`.
+    // The condition is optional.
+    pub fn parse(source: &str, alphabet: &Alphabet) -> Result<ParametricRule, ParseError> {
+        let arrow = find_top_level(source, "->")
+            .ok_or_else(|| ParseError::new(0, "expected '->'"))?;
+        let (lhs, successor_source) = (&source[..arrow], &source[arrow + 2..]);
+
+        let (predecessor_source, condition_source) = match find_top_level(lhs, ":") {
+            Some(colon) => (&lhs[..colon], Some((colon + 1, &lhs[colon + 1..]))),
+            None => (lhs, None),
+        };
+
+        let (predecessor, formals) = parse_predecessor(predecessor_source, alphabet)?;
+
+        let condition = match condition_source {
+            Some((offset, condition)) if !condition.trim().is_empty() => Some(
+                Expr::parse(condition, &formals).map_err(|e| e.offset(offset))?,
+            ),
+            Some((offset, _)) => return Err(ParseError::new(offset, "empty condition")),
+            None => None,
+        };
+
+        let successor = parse_templates(successor_source, &formals, alphabet)
+            .map_err(|e| e.offset(arrow + 2))?;
+
+        Ok(ParametricRule { predecessor, formals, condition, successor })
+    }
+
+    pub fn matches(&self, module: &Module) -> bool {
+        module.symbol == self.predecessor
+            && module.params.len() == self.formals.len()
+            && match &self.condition {
+                Some(condition) => condition.eval(&module.params) != 0.0,
+                None => true,
+            }
+    }
+
+    pub fn apply(&self, module: &Module, out: &mut Vec<Module>) {
+        for template in self.successor.iter() {
+            out.push(Module {
+                symbol: template.symbol,
+                params: template.args.iter().map(|arg| arg.eval(&module.params)).collect(),
+            });
+        }
+    }
+}
+
+// Finds `pattern` outside of any parentheses
+fn find_top_level(source: &str, pattern: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 && source[i..].starts_with(pattern) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_predecessor(source: &str, alphabet: &Alphabet) -> Result<(Symbol, Vec<String>), ParseError> {
+    let trimmed = source.trim_start();
+    let offset = source.len() - trimmed.len();
+    let (symbol, len) = match alphabet.next_symbol(trimmed) {
+        Some(next) => next,
+        None => return Err(ParseError::new(offset, "missing predecessor")),
+    };
+    let rest = trimmed[len..].trim_end();
+    if rest.is_empty() {
+        return Ok((symbol, Vec::new()));
+    }
+    if !rest.starts_with('(') || !rest.ends_with(')') {
+        return Err(ParseError::new(offset + len, "expected a single predecessor module"));
+    }
+    let mut formals = Vec::new();
+    for name in rest[1..rest.len() - 1].split(',') {
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(ParseError::new(offset, format!("invalid parameter name '{}'", name)));
+        }
+        formals.push(name.to_string());
+    }
+    Ok((symbol, formals))
+}
+
+// Splits `A(x+1,y)B` into modules, parsing the arguments with `parse_arg`
+fn parse_module_list<T>(
+    source: &str,
+    alphabet: &Alphabet,
+    mut parse_arg: impl FnMut(&str) -> Result<T, ParseError>,
+) -> Result<Vec<(Symbol, Vec<T>)>, ParseError> {
+    let mut modules = Vec::new();
+    let mut position = 0;
+
+    while let Some((symbol, len)) = alphabet.next_symbol(&source[position..]) {
+        position += len;
+        if source[position - len..position].trim().is_empty() {
+            continue;
+        }
+        let mut args = Vec::new();
+        if source[position..].starts_with('(') {
+            let open = position;
+            let mut depth = 1;
+            let mut arg_start = open + 1;
+            let mut chars = source[open + 1..].char_indices().map(|(i, c)| (open + 1 + i, c));
+            loop {
+                match chars.next() {
+                    Some((_, '(')) => depth += 1,
+                    Some((i, ')')) if depth == 1 => {
+                        args.push(parse_arg(&source[arg_start..i]).map_err(|e| e.offset(arg_start))?);
+                        position = i + 1;
+                        break;
+                    }
+                    Some((_, ')')) => depth -= 1,
+                    Some((i, ',')) if depth == 1 => {
+                        args.push(parse_arg(&source[arg_start..i]).map_err(|e| e.offset(arg_start))?);
+                        arg_start = i + 1;
+                    }
+                    Some(_) => {}
+                    None => return Err(ParseError::new(open, "unclosed '('")),
+                }
+            }
+        }
+        modules.push((symbol, args));
+    }
+    Ok(modules)
+}
+
+fn parse_templates(
+    source: &str,
+    formals: &[String],
+    alphabet: &Alphabet,
+) -> Result<Vec<ModuleTemplate>, ParseError> {
+    let modules = parse_module_list(source, alphabet, |arg| Expr::parse(arg, formals))?;
+    Ok(modules
+        .into_iter()
+        .map(|(symbol, args)| ModuleTemplate { symbol, args })
+        .collect())
+}
+
+// Parses a parametric word such as the axiom `B(2)A(4,4)`.
+// Arguments have to be constant expressions.
+pub fn parse_modules(source: &str, alphabet: &Alphabet) -> Result<Vec<Module>, ParseError> {
+    let modules = parse_module_list(source, alphabet, |arg| Ok(Expr::parse(arg, &[])?.eval(&[])))?;
+    Ok(modules
+        .into_iter()
+        .map(|(symbol, params)| Module { symbol, params })
+        .collect())
+}
+
+pub fn produce_parametric(modules: &[Module], rules: &[ParametricRule]) -> Vec<Module> {
+    let mut out = Vec::with_capacity(modules.len());
+
+    for module in modules {
+        // The first matching rule is applied
+        match rules.iter().find(|rule| rule.matches(module)) {
+            Some(rule) => rule.apply(module, &mut out),
+            // Modules without a matching rule are simply kept
+            None => out.push(module.clone()),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, formals: &[&str], args: &[f32]) -> f32 {
+        let formals: Vec<String> = formals.iter().map(|formal| formal.to_string()).collect();
+        Expr::parse(source, &formals).unwrap().eval(args)
+    }
+
+    fn error_position(result: Result<impl fmt::Debug, ParseError>) -> usize {
+        result.unwrap_err().position
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        assert_eq!(eval("1+2*3^2", &[], &[]), 19.0);
+        assert_eq!(eval("(1+2)*3", &[], &[]), 9.0);
+        assert_eq!(eval("10-4-3", &[], &[]), 3.0);
+        assert_eq!(eval("2^3^2", &[], &[]), 512.0);
+        assert_eq!(eval("-x^2", &["x"], &[2.0]), -4.0);
+        assert_eq!(eval("2^-1", &[], &[]), 0.5);
+        assert_eq!(eval("-x*-y", &["x", "y"], &[2.0, 3.0]), 6.0);
+    }
+
+    #[test]
+    fn comparisons_and_logic_are_zero_or_one() {
+        assert_eq!(eval("1<2 && 3>=3 || 0", &[], &[]), 1.0);
+        assert_eq!(eval("!(1==1)", &[], &[]), 0.0);
+        assert_eq!(eval("t != 2", &["t"], &[2.0]), 0.0);
+        assert_eq!(eval("!t", &["t"], &[0.0]), 1.0);
+    }
+
+    #[test]
+    fn numbers_may_have_exponents() {
+        assert_eq!(eval("1e-3", &[], &[]), 0.001);
+        assert_eq!(eval("2.5E2", &[], &[]), 250.0);
+        assert_eq!(eval("3e+1*2", &[], &[]), 60.0);
+    }
+
+    #[test]
+    fn expression_errors_have_positions() {
+        assert_eq!(error_position(Expr::parse("1 +", &[])), 3);
+        assert_eq!(error_position(Expr::parse("2 * y", &[])), 4);
+        assert_eq!(error_position(Expr::parse("(1", &[])), 2);
+        assert_eq!(error_position(Expr::parse("1 2", &[])), 2);
+    }
+
+    #[test]
+    fn rule_errors_have_positions() {
+        let alphabet = Alphabet::default();
+        assert_eq!(error_position(ParametricRule::parse("A(t) F(t)", &alphabet)), 0);
+        assert_eq!(error_position(ParametricRule::parse("A(t) : -> F", &alphabet)), 6);
+        assert_eq!(error_position(ParametricRule::parse("A(t) : s>0 -> F", &alphabet)), 7);
+        assert_eq!(error_position(ParametricRule::parse("A(t) -> F(t", &alphabet)), 9);
+        assert_eq!(error_position(ParametricRule::parse("A(t) -> F(u)", &alphabet)), 10);
+    }
+
+    #[test]
+    fn guards_and_arity_select_rules() {
+        let rule = ParametricRule::parse("A(t) : t>0 -> F(t)A(t-1)", &Alphabet::default()).unwrap();
+        let a = Symbol::from('A');
+        assert!(rule.matches(&Module::new(a, vec![1.0])));
+        assert!(!rule.matches(&Module::new(a, vec![0.0])));
+        assert!(!rule.matches(&Module::new(a, vec![1.0, 2.0])));
+        assert!(!rule.matches(&Module::new(Symbol::from('B'), vec![1.0])));
+    }
+
+    #[test]
+    fn parses_words_with_constant_arguments() {
+        let mut alphabet = Alphabet::default();
+        alphabet.define("Leaf");
+        let modules = parse_modules("B(2)A(4, 1+1)Leaf(3)F", &alphabet).unwrap();
+        assert_eq!(modules, vec![
+            Module::new(Symbol::from('B'), vec![2.0]),
+            Module::new(Symbol::from('A'), vec![4.0, 2.0]),
+            Module::new(alphabet.single_symbol("Leaf").unwrap(), vec![3.0]),
+            Module::from(Symbol::from('F')),
+        ]);
+        assert_eq!(error_position(parse_modules("A(t)", &alphabet)), 2);
+    }
+
+    #[test]
+    fn produces_until_no_guard_holds() {
+        let alphabet = Alphabet::default();
+        let rules = [ParametricRule::parse("A(t) : t>0 -> F(t)A(t-1)", &alphabet).unwrap()];
+        let mut word = parse_modules("A(2)", &alphabet).unwrap();
+        for expected in ["F(2)A(1)", "F(2)F(1)A(0)", "F(2)F(1)A(0)"].iter() {
+            word = produce_parametric(&word, &rules);
+            assert_eq!(&modules_to_string(&word, &alphabet), expected);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use rand::Rng;
+
+use super::symbol::{Alphabet, Symbol};
+
+// Probabilities may deviate this much from 1.0 due to rounding
+const PROBABILITY_TOLERANCE: f32 = 1e-4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StochasticRuleError {
+    NoChoices { predecessor: String },
+    // Negative, NaN or infinite
+    InvalidWeight { predecessor: String, weight: f32 },
+    ZeroTotal { predecessor: String },
+    // Finite weights whose sum overflows
+    InfiniteTotal { predecessor: String },
+    TotalNotOne { predecessor: String, total: f32 },
+}
+
+impl StochasticRuleError {
+    pub fn predecessor(&self) -> &str {
+        match self {
+            StochasticRuleError::NoChoices { predecessor }
+            | StochasticRuleError::InvalidWeight { predecessor, .. }
+            | StochasticRuleError::ZeroTotal { predecessor }
+            | StochasticRuleError::InfiniteTotal { predecessor }
+            | StochasticRuleError::TotalNotOne { predecessor, .. } => predecessor,
+        }
+    }
+}
+
+impl fmt::Display for StochasticRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StochasticRuleError::NoChoices { predecessor } => {
+                write!(f, "no successors given for '{}'", predecessor)
+            }
+            StochasticRuleError::InvalidWeight { predecessor, weight } => {
+                write!(f, "invalid weight {} for '{}'", weight, predecessor)
+            }
+            StochasticRuleError::ZeroTotal { predecessor } => {
+                write!(f, "weights for '{}' sum to zero", predecessor)
+            }
+            StochasticRuleError::InfiniteTotal { predecessor } => {
+                write!(f, "weights for '{}' are too large to sum", predecessor)
+            }
+            StochasticRuleError::TotalNotOne { predecessor, total } => {
+                write!(f, "probabilities for '{}' sum to {} instead of 1.0", predecessor, total)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StochasticRuleError {}
+
+// Stochastic rules whose successor distributions have been validated on construction.
+// Each predecessor maps to its successors with cumulative probabilities ending in 1.0.
+// They are authored on strings and can be tokenized into symbols once validated.
+#[derive(Clone, Debug)]
+pub struct StochasticRules<K = String, S = String> {
+    rules: HashMap<K, Vec<(f32, S)>>,
+}
+
+impl<K, S> Default for StochasticRules<K, S> {
+    fn default() -> Self {
+        StochasticRules { rules: HashMap::new() }
+    }
+}
+
+impl StochasticRules {
+    // The probabilities of every predecessor have to sum to 1.0
+    pub fn from_probabilities(
+        rules: HashMap<String, Vec<(f32, String)>>,
+    ) -> Result<StochasticRules, StochasticRuleError> {
+        Self::build(rules, false)
+    }
+
+    // Relative weights, e.g. 3:1, are normalised to probabilities
+    pub fn from_weights(
+        rules: HashMap<String, Vec<(f32, String)>>,
+    ) -> Result<StochasticRules, StochasticRuleError> {
+        Self::build(rules, true)
+    }
+
+    fn build(
+        rules: HashMap<String, Vec<(f32, String)>>,
+        normalise: bool,
+    ) -> Result<StochasticRules, StochasticRuleError> {
+        let mut validated = HashMap::new();
+
+        for (predecessor, choices) in rules {
+            if choices.is_empty() {
+                return Err(StochasticRuleError::NoChoices { predecessor });
+            }
+            if let Some(&(weight, _)) = choices.iter().find(|(w, _)| !w.is_finite() || *w < 0.0) {
+                return Err(StochasticRuleError::InvalidWeight { predecessor, weight });
+            }
+
+            let total: f32 = choices.iter().map(|(weight, _)| weight).sum();
+            if total == 0.0 {
+                return Err(StochasticRuleError::ZeroTotal { predecessor });
+            }
+            if !total.is_finite() {
+                return Err(StochasticRuleError::InfiniteTotal { predecessor });
+            }
+            if !normalise && (total - 1.0).abs() > PROBABILITY_TOLERANCE {
+                return Err(StochasticRuleError::TotalNotOne { predecessor, total });
+            }
+
+            let mut cumulative = 0.0;
+            let distribution = choices
+                .into_iter()
+                .map(|(weight, successor)| {
+                    cumulative += weight / total;
+                    (cumulative, successor)
+                })
+                .collect();
+            validated.insert(predecessor, distribution);
+        }
+
+        Ok(StochasticRules { rules: validated })
+    }
+
+    // Predecessors that are not a single symbol can never match and are skipped
+    pub fn tokenize(&self, alphabet: &Alphabet) -> StochasticRules<Symbol, Vec<Symbol>> {
+        let rules = self
+            .rules
+            .iter()
+            .filter_map(|(predecessor, distribution)| {
+                let symbol = alphabet.single_symbol(predecessor)?;
+                let distribution = distribution
+                    .iter()
+                    .map(|(cumulative, successor)| (*cumulative, alphabet.tokenize(successor)))
+                    .collect();
+                Some((symbol, distribution))
+            })
+            .collect();
+        StochasticRules { rules }
+    }
+}
+
+impl<K: Hash + Eq, S> StochasticRules<K, S> {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn contains<Q: Hash + Eq + ?Sized>(&self, predecessor: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.rules.contains_key(predecessor)
+    }
+
+    // Successors of `predecessor` with their (non-cumulative) probabilities
+    pub fn choices<Q: Hash + Eq + ?Sized>(&self, predecessor: &Q) -> Option<Vec<(f32, &S)>>
+    where
+        K: Borrow<Q>,
+    {
+        self.rules.get(predecessor).map(|distribution| {
+            let mut previous = 0.0;
+            distribution
+                .iter()
+                .map(|(cumulative, successor)| {
+                    let probability = cumulative - previous;
+                    previous = *cumulative;
+                    (probability, successor)
+                })
+                .collect()
+        })
+    }
+
+    pub fn map_successors<T>(self, mut f: impl FnMut(S) -> T) -> StochasticRules<K, T> {
+        let rules = self
+            .rules
+            .into_iter()
+            .map(|(predecessor, distribution)| {
+                let distribution = distribution
+                    .into_iter()
+                    .map(|(cumulative, successor)| (cumulative, f(successor)))
+                    .collect();
+                (predecessor, distribution)
+            })
+            .collect();
+        StochasticRules { rules }
+    }
+
+    pub fn choose<Q, R>(&self, predecessor: &Q, rng: &mut R) -> Option<&S>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        R: Rng + ?Sized,
+    {
+        let distribution = self.rules.get(predecessor)?;
+        let random_val: f32 = rng.gen();
+        let chosen = distribution
+            .iter()
+            .find(|(cumulative, _)| random_val < *cumulative)
+            // Rounding may leave the last cumulative probability slightly below 1.0
+            .or_else(|| distribution.last());
+        chosen.map(|(_, successor)| successor)
+    }
+}
+
+pub fn produce_stochastic_rules<R: Rng + ?Sized>(axiom: &str, rules: &StochasticRules, rng: &mut R) -> String {
+    let mut s = String::new();
+
+    for var in axiom.chars() {
+        match rules.choose(var.to_string().as_str(), rng) {
+            Some(string) => s.push_str(string),
+            None => s.push(var),
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weights(choices: &[f32]) -> HashMap<String, Vec<(f32, String)>> {
+        let choices = choices.iter().map(|&weight| (weight, String::from("F"))).collect();
+        std::iter::once((String::from("A"), choices)).collect()
+    }
+
+    #[test]
+    fn from_weights_rejects_unusable_weights() {
+        let error = |choices: &[f32]| StochasticRules::from_weights(weights(choices)).err();
+        let predecessor = String::from("A");
+        assert_eq!(error(&[]), Some(StochasticRuleError::NoChoices { predecessor: predecessor.clone() }));
+        assert!(matches!(error(&[1.0, f32::NAN]), Some(StochasticRuleError::InvalidWeight { .. })));
+        assert!(matches!(error(&[-1.0]), Some(StochasticRuleError::InvalidWeight { .. })));
+        assert_eq!(error(&[0.0, 0.0]), Some(StochasticRuleError::ZeroTotal { predecessor: predecessor.clone() }));
+        assert_eq!(error(&[f32::MAX, f32::MAX]), Some(StochasticRuleError::InfiniteTotal { predecessor }));
+        assert_eq!(error(&[3.0, 1.0]), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+// Ids of named modules start above the largest `char`
+const FIRST_NAMED: u32 = 0x11_0000;
+
+// A single symbol of an L-system word. Characters map onto their code point,
+// so `F`, `+` or `°` need no alphabet. Named modules such as `Leaf` are
+// interned by an `Alphabet` and get ids that no `char` can have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn id(self) -> u32 {
+        self.0
+    }
+
+    // `None` for named modules
+    pub fn as_char(self) -> Option<char> {
+        std::char::from_u32(self.0)
+    }
+}
+
+impl From<char> for Symbol {
+    fn from(c: char) -> Symbol {
+        Symbol(c as u32)
+    }
+}
+
+fn single_char(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Alphabet {
+    names: Vec<String>,
+    // Longest names first, so tokenizing prefers `Leaf` over `L`
+    by_length: Vec<(String, Symbol)>,
+}
+
+impl Alphabet {
+    // Defines a named module, single characters are always symbols already
+    pub fn define(&mut self, name: &str) -> Symbol {
+        if let Some(c) = single_char(name) {
+            return Symbol::from(c);
+        }
+        if let Some(symbol) = self.symbol(name) {
+            return symbol;
+        }
+        let symbol = Symbol(FIRST_NAMED + self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.by_length.push((name.to_string(), symbol));
+        self.by_length.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        symbol
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+        match single_char(name) {
+            Some(c) => Some(Symbol::from(c)),
+            None => self
+                .by_length
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, symbol)| *symbol),
+        }
+    }
+
+    pub fn name(&self, symbol: Symbol) -> String {
+        match symbol.as_char() {
+            Some(c) => c.to_string(),
+            None => self.names[(symbol.0 - FIRST_NAMED) as usize].clone(),
+        }
+    }
+
+    // Query modules of open L-systems are named modules starting with `?`, e.g. `?E`
+    pub fn is_query(&self, symbol: Symbol) -> bool {
+        symbol.as_char().is_none() && self.names[(symbol.0 - FIRST_NAMED) as usize].starts_with('?')
+    }
+
+    // Reads the symbol at the start of `source` and returns it with its length in bytes
+    pub fn next_symbol(&self, source: &str) -> Option<(Symbol, usize)> {
+        for (name, symbol) in self.by_length.iter() {
+            if source.starts_with(name.as_str()) {
+                return Some((*symbol, name.len()));
+            }
+        }
+        source.chars().next().map(|c| (Symbol::from(c), c.len_utf8()))
+    }
+
+    // Splits `source` into symbols once, whitespace only separates symbols
+    pub fn tokenize(&self, source: &str) -> Vec<Symbol> {
+        let mut symbols = Vec::with_capacity(source.len());
+        let mut rest = source.trim_start();
+        while let Some((symbol, len)) = self.next_symbol(rest) {
+            symbols.push(symbol);
+            rest = rest[len..].trim_start();
+        }
+        symbols
+    }
+
+    pub fn to_string(&self, symbols: &[Symbol]) -> String {
+        symbols.iter().map(|&symbol| self.name(symbol)).collect()
+    }
+
+    // Rules whose predecessor is not a single symbol can never match and are skipped
+    pub fn tokenize_rules(&self, rules: &HashMap<String, String>) -> HashMap<Symbol, Vec<Symbol>> {
+        rules
+            .iter()
+            .filter_map(|(predecessor, successor)| {
+                self.single_symbol(predecessor)
+                    .map(|symbol| (symbol, self.tokenize(successor)))
+            })
+            .collect()
+    }
+
+    pub fn single_symbol(&self, source: &str) -> Option<Symbol> {
+        match self.tokenize(source).as_slice() {
+            [symbol] => Some(*symbol),
+            _ => None,
+        }
+    }
+}
+
+pub fn produce_symbols(axiom: &[Symbol], rules: &HashMap<Symbol, Vec<Symbol>>) -> Vec<Symbol> {
+    let mut s = Vec::with_capacity(axiom.len());
+
+    for symbol in axiom {
+        match rules.get(symbol) {
+            // Variables are subsituted according to their production rules
+            Some(successor) => s.extend_from_slice(successor),
+            // Constants are simply kept
+            None => s.push(*symbol),
+        }
+    }
+    s
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::symbol::Symbol;
+
+// Timed DOL-systems as described in ABOP chapter 6.2.
+//
+// Every module carries a continuous age. A symbol may have a terminal age:
+// once a module reaches it, the module is replaced by its successor, whose
+// modules start out at their own initial ages. Time left over at the moment
+// of replacement is passed on to the successor, so the result of advancing
+// by `dt` twice equals advancing by `2 * dt` once.
+//
+// Symbols with a terminal age but no rule keep aging and just stop growing,
+// symbols without a terminal age are considered mature from the start.
+
+// Replacements of one module within a single `advance`. Terminal ages that are
+// tiny compared to `dt` would otherwise replace a module millions of times.
+const MAX_DEPTH: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimedModule {
+    pub symbol: Symbol,
+    pub age: f32,
+}
+
+impl TimedModule {
+    pub fn new(symbol: Symbol, age: f32) -> TimedModule {
+        TimedModule { symbol, age }
+    }
+}
+
+impl From<Symbol> for TimedModule {
+    fn from(symbol: Symbol) -> TimedModule {
+        TimedModule { symbol, age: 0.0 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimedRuleError {
+    // Zero, negative, NaN or infinite
+    InvalidTerminalAge { symbol: Symbol, terminal_age: f32 },
+    // Negative, NaN or infinite
+    InvalidInitialAge { symbol: Symbol, age: f32 },
+    // The module would be replaced again as soon as it is produced
+    InitialAgeNotBelowTerminal { symbol: Symbol, age: f32, terminal_age: f32 },
+}
+
+impl fmt::Display for TimedRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimedRuleError::InvalidTerminalAge { symbol, terminal_age } => {
+                write!(f, "invalid terminal age {} for '{}'", terminal_age, name(*symbol))
+            }
+            TimedRuleError::InvalidInitialAge { symbol, age } => {
+                write!(f, "invalid initial age {} for '{}'", age, name(*symbol))
+            }
+            TimedRuleError::InitialAgeNotBelowTerminal { symbol, age, terminal_age } => write!(
+                f,
+                "initial age {} of '{}' is not below its terminal age {}",
+                age, name(*symbol), terminal_age
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TimedRuleError {}
+
+// Named modules need the alphabet for their name, their id has to do
+fn name(symbol: Symbol) -> String {
+    match symbol.as_char() {
+        Some(c) => c.to_string(),
+        None => format!("#{}", symbol.id()),
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TimedRules {
+    terminal_ages: HashMap<Symbol, f32>,
+    rules: HashMap<Symbol, Vec<TimedModule>>,
+}
+
+impl TimedRules {
+    // A module of `symbol` grows until it reaches `terminal_age`
+    pub fn lifetime(&mut self, symbol: Symbol, terminal_age: f32) -> Result<(), TimedRuleError> {
+        self.check(symbol, terminal_age, &[])?;
+        self.terminal_ages.insert(symbol, terminal_age);
+        Ok(())
+    }
+
+    // Replaces `predecessor` once it reaches `terminal_age`
+    pub fn rule(
+        &mut self,
+        predecessor: Symbol,
+        terminal_age: f32,
+        successor: Vec<TimedModule>,
+    ) -> Result<(), TimedRuleError> {
+        self.check(predecessor, terminal_age, &successor)?;
+        self.terminal_ages.insert(predecessor, terminal_age);
+        self.rules.insert(predecessor, successor);
+        Ok(())
+    }
+
+    // Nothing changes unless giving `symbol` the `terminal_age` and adding
+    // `successor` leaves every successor starting below its terminal age
+    fn check(&self, symbol: Symbol, terminal_age: f32, successor: &[TimedModule]) -> Result<(), TimedRuleError> {
+        if !(terminal_age > 0.0 && terminal_age.is_finite()) {
+            return Err(TimedRuleError::InvalidTerminalAge { symbol, terminal_age });
+        }
+        let terminal_age_of = |module: &TimedModule| {
+            if module.symbol == symbol {
+                Some(terminal_age)
+            } else {
+                self.terminal_age(module.symbol)
+            }
+        };
+        for module in self.rules.values().flatten().chain(successor) {
+            if !(module.age >= 0.0 && module.age.is_finite()) {
+                return Err(TimedRuleError::InvalidInitialAge { symbol: module.symbol, age: module.age });
+            }
+            if let Some(terminal_age) = terminal_age_of(module) {
+                if module.age >= terminal_age {
+                    return Err(TimedRuleError::InitialAgeNotBelowTerminal {
+                        symbol: module.symbol,
+                        age: module.age,
+                        terminal_age,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn terminal_age(&self, symbol: Symbol) -> Option<f32> {
+        self.terminal_ages.get(&symbol).copied()
+    }
+
+    // How far a module has grown towards its terminal age, from 0.0 to 1.0
+    pub fn growth(&self, module: &TimedModule) -> f32 {
+        match self.terminal_age(module.symbol) {
+            Some(terminal_age) => (module.age / terminal_age).clamp(0.0, 1.0),
+            None => 1.0,
+        }
+    }
+
+    // Ages every module by `dt` and replaces the ones reaching their terminal age
+    pub fn advance(&self, word: &[TimedModule], dt: f32) -> Vec<TimedModule> {
+        let mut out = Vec::with_capacity(word.len());
+        for module in word {
+            self.expand(TimedModule::new(module.symbol, module.age + dt), &mut out);
+        }
+        out
+    }
+
+    // Depth first, so the successor keeps its order
+    fn expand(&self, module: TimedModule, out: &mut Vec<TimedModule>) {
+        let mut stack = vec![(module, 0)];
+        while let Some((module, depth)) = stack.pop() {
+            match (self.rules.get(&module.symbol), self.terminal_age(module.symbol)) {
+                (Some(successor), Some(terminal_age)) if module.age >= terminal_age && depth < MAX_DEPTH => {
+                    let overflow = module.age - terminal_age;
+                    for child in successor.iter().rev() {
+                        stack.push((TimedModule::new(child.symbol, child.age + overflow), depth + 1));
+                    }
+                }
+                _ => out.push(module),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(source: &str) -> Vec<TimedModule> {
+        source.chars().map(|c| TimedModule::from(Symbol::from(c))).collect()
+    }
+
+    fn rules() -> TimedRules {
+        let mut rules = TimedRules::default();
+        let mut successor = word("F[A]A");
+        successor[3].age = 0.2;
+        rules.rule(Symbol::from('A'), 0.75, successor).unwrap();
+        rules.lifetime(Symbol::from('F'), 1.3).unwrap();
+        rules
+    }
+
+    #[test]
+    fn small_steps_equal_one_large_step() {
+        let rules = rules();
+        let mut stepped = word("A");
+        for _ in 0..30 {
+            stepped = rules.advance(&stepped, 0.1);
+        }
+        let at_once = rules.advance(&word("A"), 3.0);
+
+        assert_eq!(stepped.len(), at_once.len());
+        for (a, b) in stepped.iter().zip(at_once.iter()) {
+            assert_eq!(a.symbol, b.symbol);
+            assert!((a.age - b.age).abs() < 1e-4, "{} != {}", a.age, b.age);
+        }
+    }
+
+    #[test]
+    fn invalid_ages_are_rejected() {
+        let (a, b) = (Symbol::from('A'), Symbol::from('B'));
+        let mut rules = TimedRules::default();
+        for terminal_age in [0.0, -1.0, f32::NAN, f32::INFINITY].iter() {
+            assert!(matches!(
+                rules.lifetime(a, *terminal_age),
+                Err(TimedRuleError::InvalidTerminalAge { .. })
+            ));
+        }
+        assert_eq!(
+            rules.rule(a, 1.0, vec![TimedModule::new(b, -0.5)]),
+            Err(TimedRuleError::InvalidInitialAge { symbol: b, age: -0.5 })
+        );
+        assert_eq!(
+            rules.rule(a, 1.0, vec![TimedModule::new(a, 1.0)]),
+            Err(TimedRuleError::InitialAgeNotBelowTerminal { symbol: a, age: 1.0, terminal_age: 1.0 })
+        );
+
+        // Rejected changes leave the rules as they were
+        rules.rule(a, 1.0, vec![TimedModule::new(b, 0.5)]).unwrap();
+        assert!(rules.lifetime(b, 0.5).is_err());
+        assert_eq!(rules.terminal_age(b), None);
+        assert_eq!(rules.terminal_age(a), Some(1.0));
+    }
+
+    #[test]
+    fn tiny_terminal_ages_stop_replacing() {
+        let a = Symbol::from('A');
+        let mut rules = TimedRules::default();
+        rules.rule(a, 1e-6, word("A")).unwrap();
+        assert_eq!(rules.advance(&word("A"), 3.0).len(), 1);
+    }
+}
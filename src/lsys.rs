@@ -0,0 +1,248 @@
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use cgmath::Deg;
+use ::palette::named::FORESTGREEN;
+
+mod l_system;
+use crate::l_system::*;
+mod turtle;
+use crate::turtle::{Geometry, Turtle, TurtleInterpreter, write_obj, write_png, write_svg};
+
+// Renders an L-system to PNG, SVG or OBJ without opening a window:
+//
+//     lsys hilbert -o hilbert.svg
+//     lsys grammars/stochastic_plant.lsys -i 5 --seed 7 -o plant.png
+//     lsys grammars/bush_3d.lsys -o bush.obj
+//
+// The output format follows the extension of the output path. OBJ output
+// comes with an MTL file of the same name.
+
+const USAGE: &str = "\
+usage: lsys <grammar file | preset> -o <output.png | output.svg | output.obj> [options]
+
+options:
+    -i, --iterations <n>   number of generations, defaults to the grammar's
+    -a, --angle <deg>      turning angle, defaults to the grammar's
+    -s, --seed <n>         seed for stochastic rules, defaults to the grammar's
+    -t, --table <name>     rule table applied in every step instead of the schedule
+    -e, --tropism <e>      bend branches down by e after every step, up if negative
+    -l, --length <l>       line length of F and f without a parameter, defaults to 10
+    -w, --width <w>        line width, defaults to 2
+        --legacy-ampersand read & as swapping + and - instead of pitching down,
+                           also set by 'legacy_ampersand: true' in the grammar
+        --size <px>        width and height of PNG output, defaults to 1024
+    -h, --help             print this message";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Png,
+    Svg,
+    Obj,
+}
+
+// Checked before anything is derived, deep derivations take a while
+fn format(output: &Path) -> Option<Format> {
+    let extension = output.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some(Format::Png),
+        "svg" => Some(Format::Svg),
+        "obj" => Some(Format::Obj),
+        _ => None,
+    }
+}
+
+struct Options {
+    source: String,
+    output: PathBuf,
+    format: Format,
+    iterations: Option<usize>,
+    angle: Option<f32>,
+    seed: Option<u64>,
+    table: Option<String>,
+    tropism: f32,
+    line_length: f32,
+    line_width: f32,
+    legacy_ampersand: bool,
+    size: u32,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("lsys: {}\n\n{}", message, USAGE);
+    exit(1);
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_else(|| fail(&format!("missing value for '{}'", flag)));
+    value
+        .parse()
+        .unwrap_or_else(|_| fail(&format!("invalid value '{}' for '{}'", value, flag)))
+}
+
+fn parse_options() -> Options {
+    let mut source = None;
+    let mut output = None;
+    let mut iterations = None;
+    let mut angle = None;
+    let mut seed = None;
+    let mut table = None;
+    let mut tropism = 0.0;
+    let mut line_length = 10.0;
+    let mut line_width = 2.0;
+    let mut legacy_ampersand = false;
+    let mut size = 1024;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}\n\npresets: {}", USAGE, PRESETS.join(", "));
+                exit(0);
+            }
+            "-o" | "--output" => output = Some(parse_value::<PathBuf>(&arg, args.next())),
+            "-i" | "--iterations" => iterations = Some(parse_value(&arg, args.next())),
+            "-a" | "--angle" => angle = Some(parse_value(&arg, args.next())),
+            "-s" | "--seed" => seed = Some(parse_value(&arg, args.next())),
+            "-t" | "--table" => table = Some(parse_value(&arg, args.next())),
+            "-e" | "--tropism" => tropism = parse_value(&arg, args.next()),
+            "-l" | "--length" => line_length = parse_value(&arg, args.next()),
+            "-w" | "--width" => line_width = parse_value(&arg, args.next()),
+            "--legacy-ampersand" => legacy_ampersand = true,
+            "--size" => size = parse_value(&arg, args.next()),
+            _ if arg.starts_with('-') => fail(&format!("unknown option '{}'", arg)),
+            _ if source.is_none() => source = Some(arg),
+            _ => fail(&format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let output = output.unwrap_or_else(|| fail("missing output path"));
+    let format = format(&output).unwrap_or_else(|| fail("the output path has to end in .png, .svg or .obj"));
+    Options {
+        source: source.unwrap_or_else(|| fail("missing grammar file or preset")),
+        output,
+        format,
+        iterations,
+        angle,
+        seed,
+        table,
+        tropism,
+        line_length,
+        line_width,
+        legacy_ampersand,
+        size,
+    }
+}
+
+// Presets take precedence over files of the same name
+fn load(source: &str) -> LSystem {
+    if let Some(l_system) = preset(source) {
+        return l_system;
+    }
+    load_grammar(Path::new(source)).unwrap_or_else(|e| {
+        eprintln!("lsys: failed to load {}: {}", source, e);
+        exit(1);
+    })
+}
+
+fn render(l_system: &LSystem, options: &Options) -> Geometry {
+    let iterations = options.iterations.unwrap_or(l_system.iterations);
+    let turtle = Turtle {
+        thickness: options.line_width,
+        color: FORESTGREEN,
+        turn_angle: Deg(options.angle.unwrap_or(l_system.angle)),
+        line_length: options.line_length,
+        palette: l_system.palette.clone(),
+        susceptibility: options.tropism,
+        ..Turtle::default()
+    };
+    let interpreter = if options.legacy_ampersand || l_system.legacy_ampersand {
+        TurtleInterpreter::abop_legacy()
+    } else {
+        TurtleInterpreter::abop()
+    };
+
+    if !l_system.parametric_rules.is_empty() {
+        let derived = l_system.derive_parametric(iterations);
+        let modules = derived.and_then(|word| l_system.interpret_parametric(&word)).unwrap_or_else(|e| {
+            eprintln!("lsys: invalid axiom: {}", e);
+            exit(1);
+        });
+        return interpreter.interpret_modules(turtle, &modules);
+    }
+
+    let table = options.table.as_deref();
+    if let Some(name) = table {
+        if !l_system.tables.contains_key(name) {
+            fail(&format!("unknown table '{}'", name));
+        }
+    }
+    // A table given on the command line replaces the grammar's schedule
+    let path = match table {
+        Some(_) => l_system.derive_with_tables(iterations, |_| table),
+        None => l_system.derive(iterations),
+    };
+    interpreter.interpret(turtle, l_system.interpret(path))
+}
+
+fn main() {
+    let options = parse_options();
+    let mut l_system = load(&options.source);
+    if let Some(seed) = options.seed {
+        l_system.seed = seed;
+    }
+
+    let geometry = render(&l_system, &options);
+    // Symbols without turtle commands draw nothing, don't write an empty file
+    if geometry.is_empty() {
+        eprintln!("lsys: {} draws nothing, check that its symbols are turtle commands", options.source);
+        exit(1);
+    }
+    let result = match options.format {
+        Format::Svg => write_svg(&geometry, &options.output).map_err(|e| e.to_string()),
+        Format::Obj => write_obj(&geometry, &options.output).map_err(|e| e.to_string()),
+        Format::Png => write_png(&geometry, options.size, options.size, &options.output).map_err(|e| e.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("lsys: failed to write {}: {}", options.output.display(), e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(source: &str) -> Options {
+        Options {
+            source: String::from(source),
+            output: PathBuf::from("out.svg"),
+            format: Format::Svg,
+            iterations: None,
+            angle: None,
+            seed: None,
+            table: None,
+            tropism: 0.0,
+            line_length: 10.0,
+            line_width: 2.0,
+            legacy_ampersand: false,
+            size: 1024,
+        }
+    }
+
+    #[test]
+    fn the_format_follows_the_extension() {
+        assert_eq!(format(Path::new("out/plant.PNG")), Some(Format::Png));
+        assert_eq!(format(Path::new("plant.svg")), Some(Format::Svg));
+        assert_eq!(format(Path::new("plant.obj")), Some(Format::Obj));
+        assert_eq!(format(Path::new("plant.jpg")), None);
+        assert_eq!(format(Path::new("plant")), None);
+    }
+
+    #[test]
+    fn every_preset_draws_something() {
+        for name in PRESETS {
+            let geometry = render(&preset(name).unwrap(), &options(name));
+            assert!(!geometry.is_empty(), "{} draws nothing", name);
+        }
+    }
+}
@@ -1,10 +1,12 @@
 mod l_system;
 mod turtle;
 mod capture;
+mod draw;
 use crate::l_system::*;
-use crate::turtle::Turtle;
+use crate::turtle::{Camera, Geometry, Projection, Turtle, TurtleInterpreter, write_obj, write_svg};
 use crate::capture::{
-    capture_path_timestamp
+    capture_path_timestamp,
+    export_path_timestamp
 };
 
 use nannou::{
@@ -15,33 +17,78 @@ use nannou::{
         widget::Slider
     }};
 
+// Zoom range of the slider, the mouse wheel can zoom in further. Zero would
+// collapse the drawing.
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 5.0;
+
 fn main() {
     nannou::app(model).update(update).run();
 }
 
 
-fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
+fn window_event(app: &App, model: &mut Model, event: WindowEvent) {
     match event {
         KeyPressed(key) => {
             match key {
                 Key::H => { model.hide_ui = !model.hide_ui; }
                 Key::C => { model.capture_image = true; }
+                Key::S => { model.export_svg = true; }
+                Key::O => { model.export_obj = true; }
                 _ => {}
             }
         }
         KeyReleased(_key) => {}
-        MouseMoved(_pos) => {}
+        MouseMoved(pos) => {
+            // Dragging orbits the camera, half a degree per point
+            if let Some(last) = model.drag {
+                let delta = pos - last;
+                model.camera.orbit(Deg(-delta.x * 0.5), Deg(-delta.y * 0.5));
+                model.drag = Some(pos);
+            }
+        }
+        MousePressed(MouseButton::Left) => {
+            let over_ui = !model.hide_ui && model.ui.global_input().current.widget_under_mouse.is_some();
+            if !over_ui {
+                model.drag = Some(app.mouse.position());
+            }
+        }
         MousePressed(_button) => {}
-        MouseReleased(_button) => {}
+        MouseReleased(_button) => { model.drag = None; }
         MouseEntered => {}
-        MouseExited => {}
-        MouseWheel(_amount, _phase) => {}
+        MouseExited => { model.drag = None; }
+        MouseWheel(amount, _phase) => {
+            let lines = match amount {
+                MouseScrollDelta::LineDelta(_, y) => y,
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+            };
+            model.camera.zoom = (model.camera.zoom * 1.1f32.powf(lines)).max(MIN_ZOOM);
+        }
         Moved(_pos) => {}
         Resized(_size) => {}
         Touch(_touch) => {}
         TouchPressure(_pressure) => {}
         HoveredFile(_path) => {}
-        DroppedFile(_path) => {}
+        DroppedFile(path) => {
+            // Grammar files can be dropped onto the window
+            match load_grammar(&path) {
+                Ok(l_system) => {
+                    model.turn_angle = l_system.angle;
+                    model.iterations = l_system.iterations as i32;
+                    // The grammar's schedule if it has one, otherwise its first table
+                    model.active_table = if l_system.schedule.is_empty() {
+                        l_system.tables.keys().next().cloned()
+                    } else {
+                        None
+                    };
+                    model.production = production(&l_system, l_system.iterations, model.active_table.as_deref());
+                    model.interpreter = interpreter(l_system.legacy_ampersand);
+                    model.l_system = l_system;
+                    model.stale = true;
+                }
+                Err(e) => println!("Failed to load {}: {}", path.display(), e),
+            }
+        }
         HoveredFileCancelled => {}
         Focused => {}
         Unfocused => {}
@@ -53,26 +100,79 @@ struct Model {
     ui: Ui,
     ids: Ids,
     turn_angle: f32,
-    roll: f32,
-    pitch: f32,
-    yaw: f32,
-    scale: f32,
+    camera: Camera,
+    // Where the mouse was while dragging to orbit the camera
+    drag: Option<Point2>,
+    // Susceptibility to gravity, negative values bend branches upwards
+    tropism: f32,
     l_system: LSystem,
     iterations: i32,
-    production: String,
+    // Rule table of a table L-system that is applied in every step,
+    // `None` follows the grammar's schedule
+    active_table: Option<String>,
+    production: Production,
+    interpreter: TurtleInterpreter,
+    // The plant as recorded by the turtle, rebuilt once it is `stale`
+    recorded: Geometry,
+    stale: bool,
+    // `recorded` as seen through `projected_with`, projected again when the camera moves
+    projected: Geometry,
+    projected_with: Option<Camera>,
     capture_image: bool,
+    export_svg: bool,
+    export_obj: bool,
     hide_ui: bool,
 }
 
+// Deterministic systems are derived lazily while rendering, stochastic and
+// parametric ones and those following a schedule have to be derived up front
+enum Production {
+    Lazy { rules: CompiledRules, axiom: Vec<u16> },
+    Derived(Vec<Symbol>),
+    Parametric(Vec<Module>),
+}
+
+fn production(l_system: &LSystem, iterations: usize, table: Option<&str>) -> Production {
+    if !l_system.parametric_rules.is_empty() {
+        let derived = l_system.derive_parametric(iterations);
+        let modules = derived.and_then(|word| l_system.interpret_parametric(&word)).unwrap_or_else(|e| {
+            println!("Invalid axiom: {}", e);
+            Vec::new()
+        });
+        Production::Parametric(modules)
+    } else if table.is_none() && !l_system.schedule.is_empty() {
+        Production::Derived(l_system.derive(iterations))
+    } else if l_system.stochastic_rules.is_empty() {
+        let (rules, axiom) = l_system.compile_table(table);
+        Production::Lazy { rules, axiom }
+    } else {
+        Production::Derived(l_system.derive_with_tables(iterations, |_| table))
+    }
+}
+
+// The ABOP commands, with the former `&` for grammars that ask for it
+fn interpreter(legacy_ampersand: bool) -> TurtleInterpreter {
+    if legacy_ampersand {
+        TurtleInterpreter::abop_legacy()
+    } else {
+        TurtleInterpreter::abop()
+    }
+}
+
 widget_ids! {
     struct Ids {
         turn_angle,
-        roll,
-        pitch,
         yaw,
-        scale,
+        pitch,
+        zoom,
+        perspective,
+        legacy_ampersand,
+        tropism,
         capture_image,
+        export_svg,
+        export_obj,
         iterations,
+        table,
     }
 }
 
@@ -90,29 +190,64 @@ fn model(app: &App) -> Model {
     let ids = Ids::new(ui.widget_id_generator());
 
     let l_system = tree_3d();
-
-    let mut production = l_system.axiom.clone();
-    for _ in 0..3 {
-        production = produce(&production, &l_system.production_rules)
-    }
+    let production = production(&l_system, l_system.iterations, None);
 
     Model {
         ui,
         ids,
         turn_angle: l_system.angle,
-        iterations: 4,
-        roll: 0.0,
-        pitch: 0.0,
-        yaw: 0.0,
-        scale: 1.0,
+        iterations: l_system.iterations as i32,
+        active_table: None,
+        camera: Camera::default(),
+        drag: None,
+        tropism: 0.0,
         production,
+        interpreter: interpreter(l_system.legacy_ampersand),
         l_system,
+        recorded: Geometry::default(),
+        stale: true,
+        projected: Geometry::default(),
+        projected_with: None,
         capture_image: false,
+        export_svg: false,
+        export_obj: false,
         hide_ui: false,
     }
 }
 
 fn update(app: &App, model: &mut Model, _update: Update) {
+    widgets(app, model);
+
+    // Deriving and projecting only happen when something changed, not every frame
+    if model.stale {
+        model.recorded = record(model);
+        model.projected_with = None;
+        model.stale = false;
+    }
+    if model.projected_with != Some(model.camera) {
+        model.projected = project(&model.recorded, model.camera);
+        model.projected_with = Some(model.camera);
+    }
+
+    if model.export_svg {
+        let file_path = export_path_timestamp(app, "svg");
+        if let Err(e) = write_svg(&model.projected, &file_path) {
+            println!("Failed to write {}: {}", file_path.display(), e);
+        }
+        model.export_svg = false;
+    }
+    // The plant itself rather than the view, 3D packages bring their own camera
+    if model.export_obj {
+        let file_path = export_path_timestamp(app, "obj");
+        if let Err(e) = write_obj(&model.recorded, &file_path) {
+            println!("Failed to write {}: {}", file_path.display(), e);
+        }
+        model.export_obj = false;
+    }
+}
+
+// The UI borrows the model until it is dropped at the end
+fn widgets(app: &App, model: &mut Model) {
     let ui = &mut model.ui.set_widgets();
 
     fn slider(value: f32, min: f32, max: f32) -> Slider<'static, f32> {
@@ -129,25 +264,46 @@ fn update(app: &App, model: &mut Model, _update: Update) {
         .label("Branch Angle")
         .set(model.ids.turn_angle, ui);
 
-    let roll_slider = slider(0.0, 0.0, 360.0)
+    // The camera can also be orbited by dragging and zoomed with the mouse wheel
+    let yaw_slider = slider(model.camera.yaw.0, 0.0, 360.0)
         .down(10.0)
-        .label("Roll")
-        .set(model.ids.roll, ui);
+        .label("Orbit")
+        .set(model.ids.yaw, ui);
     
-    let pitch_slider = slider(0.0, 0.0, 360.0)
+    let pitch_slider = slider(model.camera.pitch.0, -89.0, 89.0)
         .down(10.0)
-        .label("Pitch")
+        .label("Elevation")
         .set(model.ids.pitch, ui);
     
-    let yaw_slider = slider(0.0, 0.0, 360.0)
+    let zoom_slider = slider(model.camera.zoom, MIN_ZOOM, MAX_ZOOM)
         .down(10.0)
-        .label("Yaw")
-        .set(model.ids.yaw, ui);
-    
-    let scale_slider = slider(model.scale, 0.0, 5.0)
+        .label("Zoom")
+        .set(model.ids.zoom, ui);
+
+    let perspective_toggle = widget::Toggle::new(model.camera.projection == Projection::Perspective)
+        .w_h(200.0, 30.0)
         .down(10.0)
-        .label("Scale")
-        .set(model.ids.scale, ui);
+        .label("Perspective")
+        .label_font_size(15)
+        .label_rgb(1.0, 1.0, 1.0)
+        .rgb(0.3, 0.3, 0.3)
+        .border(0.0)
+        .set(model.ids.perspective, ui);
+
+    let legacy_ampersand_toggle = widget::Toggle::new(model.l_system.legacy_ampersand)
+        .w_h(200.0, 30.0)
+        .down(10.0)
+        .label("Legacy &")
+        .label_font_size(15)
+        .label_rgb(1.0, 1.0, 1.0)
+        .rgb(0.3, 0.3, 0.3)
+        .border(0.0)
+        .set(model.ids.legacy_ampersand, ui);
+
+    let tropism_slider = slider(model.tropism, -0.5, 0.5)
+        .down(10.0)
+        .label("Tropism")
+        .set(model.ids.tropism, ui);
 
     let iterations_slider = widget::Slider::new(model.iterations as f32, 0.0, 10.0)
         .w_h(200.0, 30.0)
@@ -159,6 +315,20 @@ fn update(app: &App, model: &mut Model, _update: Update) {
         .label("Iterations")
         .set(model.ids.iterations, ui);
 
+    let mut tables = vec![String::from(if model.l_system.schedule.is_empty() { "No table" } else { "Schedule" })];
+    tables.extend(model.l_system.tables.keys().cloned());
+    let selected_table = model.active_table.as_ref()
+        .and_then(|active| tables.iter().position(|table| table == active))
+        .unwrap_or(0);
+    let table_list = widget::DropDownList::new(&tables, Some(selected_table))
+        .w_h(200.0, 30.0)
+        .label_font_size(15)
+        .label_rgb(1.0, 1.0, 1.0)
+        .rgb(0.3, 0.3, 0.3)
+        .border(0.0)
+        .down(10.0)
+        .set(model.ids.table, ui);
+
     for _click in widget::Button::new()
         .down(10.0)
         .w_h(200.0, 30.0)
@@ -170,19 +340,59 @@ fn update(app: &App, model: &mut Model, _update: Update) {
         .set(model.ids.capture_image, ui) {
         model.capture_image = true;
     }
+
+    for _click in widget::Button::new()
+        .down(10.0)
+        .w_h(200.0, 30.0)
+        .label("Export SVG [S]")
+        .label_font_size(15)
+        .rgb(0.3, 0.3, 0.3)
+        .label_rgb(1.0, 1.0, 1.0)
+        .border(0.0)
+        .set(model.ids.export_svg, ui) {
+        model.export_svg = true;
+    }
+
+    for _click in widget::Button::new()
+        .down(10.0)
+        .w_h(200.0, 30.0)
+        .label("Export OBJ [O]")
+        .label_font_size(15)
+        .rgb(0.3, 0.3, 0.3)
+        .label_rgb(1.0, 1.0, 1.0)
+        .border(0.0)
+        .set(model.ids.export_obj, ui) {
+        model.export_obj = true;
+    }
         
-    for value in angle_slider { model.turn_angle = value; }
-    for value in roll_slider { model.roll = value; }
-    for value in pitch_slider { model.pitch = value; }
-    for value in yaw_slider { model.yaw = value; }
-    for value in scale_slider { model.scale = value; }
+    for value in angle_slider {
+        model.turn_angle = value;
+        model.stale = true;
+    }
+    for value in yaw_slider { model.camera.yaw = Deg(value); }
+    for value in pitch_slider { model.camera.pitch = Deg(value); }
+    for value in zoom_slider { model.camera.zoom = value; }
+    for value in perspective_toggle {
+        model.camera.projection = if value { Projection::Perspective } else { Projection::Orthographic };
+    }
+    for value in legacy_ampersand_toggle {
+        model.l_system.legacy_ampersand = value;
+        model.interpreter = interpreter(value);
+        model.stale = true;
+    }
+    for value in tropism_slider {
+        model.tropism = value;
+        model.stale = true;
+    }
     for value in iterations_slider { 
         model.iterations = value as i32; 
-        let mut production = model.l_system.axiom.clone();
-        for _ in 0..value as i32 {
-            production = produce(&production, &model.l_system.production_rules)
-        }
-        model.production = production;
+        model.production = production(&model.l_system, model.iterations as usize, model.active_table.as_deref());
+        model.stale = true;
+    }
+    if let Some(selected) = table_list {
+        model.active_table = if selected == 0 { None } else { Some(tables[selected].clone()) };
+        model.production = production(&model.l_system, model.iterations as usize, model.active_table.as_deref());
+        model.stale = true;
     }
 
     if model.capture_image {
@@ -193,12 +403,8 @@ fn update(app: &App, model: &mut Model, _update: Update) {
 }
 
 
-fn view(app: &App, model: &Model, frame: Frame) {
-    let draw = app.draw();
-    
-    draw.background().color(WHITE);
-
-    let mut turtle = Turtle{
+fn record(model: &Model) -> Geometry {
+    let turtle = Turtle{
         position: vec3(
             0.0,//app.window_rect().mid_bottom().x, 
             0.0, //app.window_rect().bottom(),
@@ -209,14 +415,38 @@ fn view(app: &App, model: &Model, frame: Frame) {
         turn_angle: Deg(model.turn_angle),
         turn_reversed: false,
         line_length: 10.0,
+        palette: model.l_system.palette.clone(),
+        susceptibility: model.tropism,
         .. Turtle::default()
     };
 
-    turtle.roll(Deg(model.roll));
-    turtle.pitch(Deg(model.pitch));
-    turtle.yaw(Deg(model.yaw));
+    match &model.production {
+        Production::Lazy { rules, axiom } => {
+            let path = rules.derivation(axiom, model.iterations as usize);
+            model.interpreter.interpret(turtle, model.l_system.interpret(path))
+        }
+        Production::Derived(path) => {
+            model.interpreter.interpret(turtle, model.l_system.interpret(path.iter().copied()))
+        }
+        Production::Parametric(modules) => model.interpreter.interpret_modules(turtle, modules),
+    }
+}
 
-    render_turtle(&draw, &model.production, turtle, model.scale);
+// The plant as seen through the camera, which orbits around its centre
+fn project(recorded: &Geometry, camera: Camera) -> Geometry {
+    let camera = match recorded.bounds() {
+        Some((min, max)) => Camera { target: (min + max) / 2.0, ..camera },
+        None => camera,
+    };
+    camera.project(recorded)
+}
+
+fn view(app: &App, model: &Model, frame: Frame) {
+    let draw = app.draw();
+    
+    draw.background().color(WHITE);
+
+    model.projected.draw(&draw);
     draw.to_frame(app, &frame).unwrap();
     
     if model.capture_image {
@@ -227,99 +457,3 @@ fn view(app: &App, model: &Model, frame: Frame) {
         model.ui.draw_to_frame(app, &frame).unwrap();
     }
 }
-
-pub fn render_turtle(draw: &Draw, path: &str, mut turtle: Turtle, scaling: f32) {
-    let turning_angle_increment = Deg(5.0);
-    let line_length_scaling_factor = 1.5;
-
-    for c in path.chars() {
-        match c.to_string().as_str() {
-            // Move forward by line length drawing a line
-            "F" => {
-                turtle.forward(draw, turtle.line_length * scaling);
-            }
-            // Move forward by line length without drawing a line
-            "f" => {
-                turtle.forward_no_draw(turtle.line_length * scaling);
-            }
-            // Turn left by turning angle
-            "+" => {
-                turtle.yaw(-turtle.turn_angle);
-            }
-            // Turn right by turning angle
-            "-" => {
-                turtle.yaw(turtle.turn_angle);
-            }
-            // Reverse direction (ie: turn by 180 degrees)
-            "|" => {
-                turtle.yaw(Deg(180.0));
-            }
-            // Push current drawing state onto stack
-            "[" => {
-                turtle.push();
-            }
-            // Pop current drawing state from the stack
-            "]" => {
-                turtle.pop();
-            }
-            // Increment the line width by line width increment
-            "#" => {
-                turtle.increment_thickness(turtle.thickness);
-            }
-            // Decrement the line width by line width increment
-            "!" => {
-                turtle.decrement_thickness(turtle.thickness);
-            }
-            // Draw a dot with line width radius
-            "@" => {
-                turtle.dot(draw, turtle.thickness);
-            }
-            // Open a polygon
-            "{" => {}
-            // Close a polygon and fill it with fill colour
-            "}" => {}
-            // Swap the meaning of + and -
-            "&" => {
-                turtle.reverse_turn();
-            }
-            // Decrement turning angle by turning angle increment
-            "(" => {
-                turtle.turn_angle -= turning_angle_increment;
-            }
-            // Increment turning angle by turning angle increment
-            ")" => {
-                turtle.turn_angle += turning_angle_increment;
-            }
-            // Multiply the line length by the line length scale factor
-            ">" => {
-                turtle.line_length *= line_length_scaling_factor;
-            }	
-            // Divide the line length by the line length scale factor
-            "<" => {
-                turtle.line_length /= line_length_scaling_factor;
-            }
-            // Pitch upwards
-            "^" => {
-                turtle.pitch(turtle.turn_angle);
-            } 
-            // Roll counterclockwise
-            "/" => {
-                turtle.roll(turtle.turn_angle);
-            }
-            // Scale linelength
-            "°" => {
-                turtle.line_length *= 0.9;
-            }
-            // X does not correspond to any drawing action and is used to control the evolution of the curve. 
-            "X" => {}
-            // Y does not correspond to any drawing action and is used to control the evolution of the curve. 
-            "Y" => {}
-            // A does not correspond to any drawing action and is used to control the evolution of the curve. 
-            "A" => {}
-            
-            _ => {
-                println!("unknown command")
-            }
-        }
-    }
-}
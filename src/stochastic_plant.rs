@@ -1,96 +1,95 @@
-use std::collections::HashMap;
 use nannou::prelude::*;
 use nannou::math::Deg;
 
 mod l_system;
-pub use crate::l_system::produce_stochastic;
+pub use crate::l_system::{stochastic_plant, LSystem, Symbol};
 mod turtle;
-pub use crate::turtle::Turtle;
+pub use crate::turtle::{Turtle, TurtleInterpreter};
+mod draw;
 mod capture;
 pub use crate::capture::capture_path_frame_count;
 
 
-pub fn render_turtle(draw: &Draw, path: &str) {
-    let mut turtle = Turtle{
+fn turtle() -> Turtle {
+    Turtle{
         position: vec3(0.0, -512.0, 0.0).into(),
         thickness: 5.0,
         color: FORESTGREEN,
-        stack: Vec::new(),
+        turn_angle: Deg(25.0),
+        line_length: 50.0,
         .. Turtle::default()
-    };
-
-    let scaling = 1.0;
-
-    for c in path.chars() {
-        match c.to_string().as_str() {
-            "a" => {
-                turtle.forward(draw, 50.0 * scaling);
-            }
-            "b" => {
-                turtle.yaw(Deg(25.0));
-            }
-            "c" => {
-                turtle.yaw(Deg(-25.0));
-            }
-            "d" => {
-                // do nothing
-            }
-            "[" => {
-                turtle.push();
-            }
-            "]" => {
-                turtle.pop();
-            }
-            _ => {
-                println!("unknown command")
-            }
-        }
     }
 }
 
+fn interpreter() -> TurtleInterpreter {
+    let mut interpreter = TurtleInterpreter::empty();
+    interpreter.register('a', |turtle, _| turtle.forward(turtle.line_length));
+    interpreter.register('b', |turtle, _| turtle.yaw(turtle.turn_angle));
+    interpreter.register('c', |turtle, _| turtle.yaw(-turtle.turn_angle));
+    // d does nothing
+    interpreter.register('[', |turtle, _| turtle.push());
+    interpreter.register(']', |turtle, _| turtle.pop());
+    interpreter
+}
+
 struct Model {
-    axiom: String,
-    path: String,
-    rules: HashMap<String, Vec<(f32, String)>>,
-    iteration_count: u32,
+    l_system: LSystem,
+    // Seed given on the command line, regrown on every restart
+    fixed_seed: Option<u64>,
+    path: Vec<Symbol>,
+    interpreter: TurtleInterpreter,
+    iteration_count: usize,
+}
+
+fn seed_argument() -> Option<u64> {
+    let argument = std::env::args().nth(1)?;
+    match argument.parse() {
+        Ok(seed) => Some(seed),
+        Err(_) => {
+            eprintln!("usage: stochastic_plant [seed], '{}' is not a seed", argument);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn model(app: &App) -> Model {
     let _window = app.new_window().size(1024,1024).view(view).build().unwrap();
 
     // Initial string: d
-    let axiom = String::from("d");
-    let mut stochastic_production_rules = HashMap::new();
-    // Replacement rules:
+    // Replacement rules, weighted 1:1:
     //    1)  d  d[cad]a   (p=50%)
     //    2)  d  d[bad]a   (p=50%)
-    let mut choices = Vec::new();
-    choices.push((0.5, String::from("d[cad]a")));
-    choices.push((0.5, String::from("d[bad]a")));
-    stochastic_production_rules.insert(String::from("d"), choices);
-
-
+    // Every plant is grown from its own seed, so it can be regenerated later
+    // with `cargo run --example stochastic_plant -- <seed>` or
+    // `lsys stochastic_plant --seed <seed>`
+    let fixed_seed = seed_argument();
+    let l_system = LSystem {
+        seed: fixed_seed.unwrap_or_else(rand::random),
+        ..stochastic_plant()
+    };
 
     Model { 
-        axiom: axiom.clone(),
-        path: axiom,
-        rules: stochastic_production_rules,
+        path: l_system.derive(0),
+        l_system,
+        fixed_seed,
+        interpreter: interpreter(),
         iteration_count: 0,
     }
 }
 
 fn update(app: &App, model: &mut Model, _update: Update) {
     if app.elapsed_frames() % 15 == 0 {
-        if model.iteration_count == 7 {
-            // Reset the plant
+        if model.iteration_count == model.l_system.iterations {
+            // Reset the plant and start over with a fresh seed, unless one was given
             model.iteration_count = 0;
-            model.path = produce_stochastic(&model.axiom, &model.rules);
+            model.l_system.seed = model.fixed_seed.unwrap_or_else(rand::random);
         }
         else {
-            // Keep growing
+            // Keep growing. Every generation draws from its own rng streams,
+            // so growing one more step repeats the steps before it exactly.
             model.iteration_count += 1;
-            model.path = produce_stochastic(&model.path, &model.rules);
         }
+        model.path = model.l_system.derive(model.iteration_count);
     }
 }
 
@@ -99,8 +98,13 @@ fn view(app: &App, model: &Model, frame: Frame) {
     
     draw.background().color(WHITE);
 
-    render_turtle(&draw, &model.path);
+    model.interpreter.interpret(turtle(), model.path.iter().copied()).draw(&draw);
     draw.text(&app.fps().to_string()).x_y(-500.0, 500.0).color(FORESTGREEN);
+    draw.text(&format!("seed {}", model.l_system.seed))
+        .x_y(-400.0, 500.0)
+        .w(200.0)
+        .left_justify()
+        .color(FORESTGREEN);
     draw.to_frame(app, &frame).unwrap();
 
     let file_path = capture_path_frame_count(app, &frame);
@@ -0,0 +1,88 @@
+use nannou::prelude::*;
+use nannou::math::Deg;
+
+mod l_system;
+pub use crate::l_system::{topiary, produce_parametric, parse_modules, LSystem, Module, Symbol};
+mod turtle;
+pub use crate::turtle::{Environment, Turtle, TurtleInterpreter, query_environment};
+mod draw;
+
+// The shape the plant is pruned to
+struct Hedge {
+    centre: Vector2,
+    radius: f32,
+}
+
+impl Environment for Hedge {
+    fn query(&mut self, _symbol: Symbol, turtle: &Turtle, params: &mut [f32]) {
+        let position = vec2(turtle.position.x, turtle.position.y);
+        let inside = position.distance(self.centre) < self.radius;
+        if let Some(param) = params.first_mut() {
+            *param = if inside { 1.0 } else { 0.0 };
+        }
+    }
+}
+
+fn turtle(l_system: &LSystem) -> Turtle {
+    Turtle{
+        position: vec3(0.0, -400.0, 0.0).into(),
+        thickness: 2.0,
+        turn_angle: Deg(l_system.angle),
+        line_length: 20.0,
+        .. Turtle::default()
+    }
+}
+
+struct Model {
+    l_system: LSystem,
+    hedge: Hedge,
+    // Query modules don't correspond to any drawing action
+    interpreter: TurtleInterpreter,
+    path: Vec<Module>,
+    iteration_count: usize,
+}
+
+fn model(app: &App) -> Model {
+    let _window = app.new_window().size(1024,1024).view(view).build().unwrap();
+
+    let l_system = topiary();
+    let path = parse_modules(&l_system.axiom, &l_system.alphabet).expect("invalid axiom");
+
+    Model {
+        l_system,
+        hedge: Hedge { centre: vec2(0.0, 50.0), radius: 350.0 },
+        interpreter: TurtleInterpreter::default(),
+        path,
+        iteration_count: 0,
+    }
+}
+
+fn update(app: &App, model: &mut Model, _update: Update) {
+    if app.elapsed_frames() % 30 == 0 && model.iteration_count < model.l_system.iterations {
+        // The environment answers the queries, then the rules react to the answers
+        let turtle = turtle(&model.l_system);
+        let alphabet = &model.l_system.alphabet;
+        query_environment(&mut model.path, alphabet, &model.interpreter, turtle, &mut model.hedge);
+        model.path = produce_parametric(&model.path, &model.l_system.parametric_rules);
+        model.iteration_count += 1;
+    }
+}
+
+fn view(app: &App, model: &Model, frame: Frame) {
+    let draw = app.draw();
+    
+    draw.background().color(WHITE);
+    draw.ellipse()
+        .xy(model.hedge.centre)
+        .radius(model.hedge.radius)
+        .no_fill()
+        .stroke(LIGHTGREY)
+        .stroke_weight(2.0);
+    model.interpreter.interpret_modules(turtle(&model.l_system), &model.path).draw(&draw);
+
+    draw.to_frame(app, &frame).unwrap();
+}
+
+fn main() {
+    nannou::app(model).update(update).run();
+}
@@ -1,23 +1,72 @@
 use std::ops::Mul;
 
 // use std::collections::HashMap;
-use nannou::{math::{Deg, Matrix3, Quaternion, cgmath::Vector3}, prelude::*};
+use cgmath::{Deg, InnerSpace, Matrix3, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use palette::named::FORESTGREEN;
 
+use crate::l_system::{Palette, Rgb8};
+
+pub mod environment;
+pub use environment::{Environment, query_environment};
+pub mod interpreter;
+pub use interpreter::TurtleInterpreter;
+pub mod geometry;
+pub use geometry::{Branch, Dot, Element, Geometry, Polygon, Segment};
+pub mod svg;
+pub use svg::{to_svg, write_svg};
+pub mod raster;
+pub use raster::{rasterize, write_png};
+pub mod camera;
+pub use camera::{Camera, Projection};
+pub mod mesh;
+pub use mesh::{Mesh, branch_mesh};
+pub mod obj;
+pub use obj::{to_obj, write_obj};
+
+// What `push` saves and `pop` restores
+#[derive(Clone, Copy, Debug)]
+pub struct TurtleState {
+    pub position: Vector3<f32>,
+    pub orientation: Matrix3<f32>,
+    pub branch: usize,
+    pub color_index: usize,
+    pub distance: f32,
+}
+
+#[derive(Clone)]
 pub struct Turtle {
     pub position: Vector3<f32>,
     pub orientation: Matrix3<f32>,
     pub thickness: f32,
     pub color: Rgb8,
-    pub stack: Vec<(Vector3<f32>, Matrix3<f32>)>,
+    // Colour polygons are filled with
+    pub fill_color: Rgb8,
+    // Replaces `color` and `fill_color` when set, see `stroke_color`
+    pub palette: Option<Palette>,
+    // Entry of an indexed palette
+    pub color_index: usize,
+    // Path length from the root, for palettes by distance
+    pub distance: f32,
+    pub stack: Vec<TurtleState>,
     pub turn_reversed: bool,
     pub turn_angle: Deg<f32>,
     pub line_length: f32,
+    // Direction branches bend towards after every step, e.g. gravity
+    pub tropism: Vector3<f32>,
+    // How strongly they bend, negative values bend away from `tropism`
+    pub susceptibility: f32,
+    // Vertices of the polygons being recorded, the innermost one last
+    pub polygon_stack: Vec<Vec<Vector3<f32>>>,
+    // Everything drawn so far
+    pub geometry: Geometry,
+    // Branch of `geometry` that is being drawn
+    pub branch: usize,
 }
 
 impl Default for Turtle {
     fn default() -> Turtle {
         Turtle{
-            position: vec3(0.0, 0.0, 0.0).into(),
+            position: Vector3::new(0.0, 0.0, 0.0),
             orientation: Matrix3::from_cols(
                 Vector3::unit_y().into(),
                 Vector3::unit_x().into(),
@@ -25,35 +74,75 @@ impl Default for Turtle {
             ),
             thickness: 2.0,
             color: FORESTGREEN,
+            fill_color: FORESTGREEN,
+            palette: None,
+            color_index: 0,
+            distance: 0.0,
             stack: Vec::new(),
             turn_angle: Deg(25.0),
             turn_reversed: false,
             line_length: 1.0,
+            tropism: Vector3::new(0.0, -1.0, 0.0),
+            susceptibility: 0.0,
+            polygon_stack: Vec::new(),
+            geometry: Geometry::default(),
+            branch: 0,
         }
     }
 }
 
 impl Turtle {
-    pub fn forward(& mut self, draw: &Draw, dist: f32) {
+    pub fn forward(& mut self, dist: f32) {
         let new_position = self.position + self.orientation.x.mul(dist);
         
-        draw.line()
-            .start(vec2(self.position.x, self.position.y))
-            .end(vec2(new_position.x, new_position.y))
-            .weight(self.thickness)
-            .color(self.color);
+        self.geometry.segments.push(Segment {
+            start: self.position,
+            end: new_position,
+            thickness: self.thickness,
+            color: self.stroke_color(),
+            branch: self.branch,
+        });
 
         self.position = new_position;
+        self.distance += dist;
+        self.bend();
     }
 
-    pub fn dot(& mut self, draw: &Draw, radius: f32) {
-        draw.ellipse()
-        .radius(radius)
-        .color(self.color);
+    pub fn dot(& mut self, radius: f32) {
+        self.geometry.dots.push(Dot {
+            position: self.position,
+            radius,
+            color: self.stroke_color(),
+            branch: self.branch,
+        });
     }
 
     pub fn forward_no_draw(& mut self, dist: f32) {
         self.position += self.orientation.x.mul(dist);
+        self.distance += dist;
+        self.bend();
+    }
+
+    // Rotates the heading H towards the tropism vector T by e·|H×T| radians
+    // around H×T, see ABOP chapter 2.2
+    fn bend(&mut self) {
+        if self.susceptibility == 0.0 {
+            return;
+        }
+        let axis = self.orientation.x.cross(self.tropism);
+        let magnitude = axis.magnitude();
+        if magnitude < f32::EPSILON {
+            return;
+        }
+
+        let rotation = Quaternion::from_axis_angle(
+            axis / magnitude,
+            Rad(self.susceptibility * magnitude),
+        );
+
+        self.orientation.x = rotation.rotate_vector(self.orientation.x);
+        self.orientation.y = rotation.rotate_vector(self.orientation.y);
+        self.orientation.z = rotation.rotate_vector(self.orientation.z);
     }
     
 
@@ -99,21 +188,101 @@ impl Turtle {
         self.orientation.z = rotation.rotate_vector(self.orientation.z);
     }
 
+    // Turns around the up vector
+    pub fn turn_around(&mut self) {
+        self.yaw(Deg(180.0));
+    }
+
+    // Rolls around the heading until the left vector is horizontal, with up
+    // pointing upwards as far as possible. Nothing happens while heading straight
+    // up or down. See ABOP chapter 1.5.
+    pub fn rotate_to_vertical(&mut self) {
+        let vertical = Vector3::unit_y();
+        let heading = self.orientation.x;
+        let horizontal = vertical.cross(heading);
+        if horizontal.magnitude() < f32::EPSILON {
+            return;
+        }
+        // Keep the handedness of the current frame
+        let handedness = heading.cross(self.orientation.y).dot(self.orientation.z).signum();
+        let left = horizontal.normalize();
+        let up = heading.cross(left).normalize() * handedness;
+        // Of the two horizontal left vectors, the one with up pointing upwards
+        let sign = if up.dot(vertical) < 0.0 { -1.0 } else { 1.0 };
+
+        self.orientation.y = left * sign;
+        self.orientation.z = up * sign;
+    }
+
+    // Starts a new branch
     pub fn push(&mut self) {
-        self.stack.push((self.position, self.orientation));
+        self.stack.push(TurtleState {
+            position: self.position,
+            orientation: self.orientation,
+            branch: self.branch,
+            color_index: self.color_index,
+            distance: self.distance,
+        });
+        self.branch = self.geometry.branch(self.branch);
     }
 
     pub fn pop(&mut self) {
         match self.stack.pop() {
-            Some(popped) => {
-                let (position, orientation) = popped;
-                self.position = position;
-                self.orientation = orientation;
+            Some(state) => {
+                self.position = state.position;
+                self.orientation = state.orientation;
+                self.branch = state.branch;
+                self.color_index = state.color_index;
+                self.distance = state.distance;
             }
             None => println!("Popped off empty stack")
         }
     }
 
+    // Polygons can be nested, each one collects its own vertices
+    pub fn begin_polygon(&mut self) {
+        self.polygon_stack.push(Vec::new());
+    }
+
+    // Records the current position as a vertex of the innermost open polygon
+    pub fn vertex(&mut self) {
+        match self.polygon_stack.last_mut() {
+            Some(vertices) => vertices.push(self.position),
+            None => println!("Recorded a vertex outside of a polygon")
+        }
+    }
+
+    // Fills the innermost open polygon with the fill colour
+    pub fn end_polygon(&mut self) {
+        match self.polygon_stack.pop() {
+            // Fewer than three vertices don't enclose an area
+            Some(vertices) if vertices.len() >= 3 => {
+                self.geometry.polygons.push(Polygon {
+                    vertices,
+                    color: self.polygon_color(),
+                    branch: self.branch,
+                });
+            }
+            Some(_) => {}
+            None => println!("Closed a polygon that was never opened")
+        }
+    }
+
+    // Colour of the next line or dot
+    pub fn stroke_color(&self) -> Rgb8 {
+        self.palette_color().unwrap_or(self.color)
+    }
+
+    // Colour of the next polygon
+    pub fn polygon_color(&self) -> Rgb8 {
+        self.palette_color().unwrap_or(self.fill_color)
+    }
+
+    fn palette_color(&self) -> Option<Rgb8> {
+        let depth = self.geometry.depth(self.branch);
+        self.palette.as_ref().map(|palette| palette.color(self.color_index, depth, self.distance))
+    }
+
     pub fn increment_thickness(&mut self, increment: f32) {
         self.thickness += increment;
     }
@@ -125,4 +294,92 @@ impl Turtle {
     pub fn reverse_turn(&mut self) {
         self.turn_reversed = !self.turn_reversed;
     }
+
+    // Timed modules grow from nothing to the full line length, `growth` runs from 0.0 to 1.0
+    pub fn grown_length(&self, growth: f32) -> f32 {
+        self.line_length * growth
+    }
+
+    // Branches open up from straight ahead to the full turning angle
+    pub fn grown_angle(&self, growth: f32) -> Deg<f32> {
+        self.turn_angle * growth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::Symbol;
+
+    fn draw(word: &str) -> Geometry {
+        TurtleInterpreter::abop().interpret(Turtle::default(), word.chars().map(Symbol::from))
+    }
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-5
+    }
+
+    #[test]
+    fn segments_belong_to_the_branch_of_the_stack() {
+        let geometry = draw("F[+F]F");
+
+        assert_eq!(geometry.branches, vec![
+            Branch { parent: None, depth: 0 },
+            Branch { parent: Some(0), depth: 1 },
+        ]);
+        let branches: Vec<usize> = geometry.segments.iter().map(|s| s.branch).collect();
+        assert_eq!(branches, vec![0, 1, 0]);
+
+        let side = geometry.segments[1];
+        assert!(close(side.start, Vector3::new(0.0, 1.0, 0.0)));
+        assert!(((side.end - side.start).magnitude() - 1.0).abs() < 1e-5);
+        // Popping returns to where the branch started
+        assert!(close(geometry.segments[2].start, Vector3::new(0.0, 1.0, 0.0)));
+        assert!(close(geometry.segments[2].end, Vector3::new(0.0, 2.0, 0.0)));
+    }
+
+    #[test]
+    fn polygons_need_three_vertices() {
+        let geometry = draw("{.f.+f.}");
+        assert!(geometry.segments.is_empty());
+        assert_eq!(geometry.polygons.len(), 1);
+        assert_eq!(geometry.polygons[0].vertices.len(), 3);
+
+        assert!(draw("{.f.}").is_empty());
+    }
+
+    #[test]
+    fn bend_turns_the_heading_towards_the_tropism_vector() {
+        let mut turtle = Turtle { susceptibility: 0.2, ..Turtle::default() };
+        turtle.yaw(Deg(-90.0));
+        assert!(close(turtle.orientation.x, Vector3::new(1.0, 0.0, 0.0)));
+
+        // |H×T| is 1, so the heading turns down by 0.2 radians after the segment
+        turtle.forward(1.0);
+        assert!(close(turtle.geometry.segments[0].end, Vector3::new(1.0, 0.0, 0.0)));
+        assert!(close(turtle.orientation.x, Vector3::new(0.2f32.cos(), -0.2f32.sin(), 0.0)));
+        assert!((turtle.orientation.y.dot(turtle.orientation.x)).abs() < 1e-5);
+
+        // Heading along the tropism vector there is nothing to bend
+        let mut turtle = Turtle { susceptibility: 0.2, ..Turtle::default() };
+        turtle.yaw(Deg(180.0));
+        turtle.forward(1.0);
+        assert!(close(turtle.orientation.x, Vector3::new(0.0, -1.0, 0.0)));
+    }
+
+    #[test]
+    fn rotate_to_vertical_levels_the_left_vector() {
+        let mut turtle = Turtle::default();
+        turtle.pitch(Deg(30.0));
+        turtle.roll(Deg(40.0));
+        turtle.yaw(Deg(20.0));
+        let heading = turtle.orientation.x;
+
+        turtle.rotate_to_vertical();
+        assert!(close(turtle.orientation.x, heading));
+        assert!(turtle.orientation.y.y.abs() < 1e-5);
+        assert!(turtle.orientation.z.y > 0.0);
+        assert!((turtle.orientation.y.magnitude() - 1.0).abs() < 1e-5);
+        assert!(turtle.orientation.y.dot(turtle.orientation.z).abs() < 1e-5);
+    }
 }
@@ -0,0 +1,219 @@
+use cgmath::{Deg, InnerSpace, Vector3};
+
+use super::{Dot, Geometry, Polygon, Segment};
+
+// Looks at recorded geometry from any direction. `Camera::project` turns 3D
+// geometry into geometry whose xy plane is the screen, so it can be drawn or
+// exported like any other. The default camera looks down the z axis, which
+// leaves x and y as they are.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Orthographic,
+    // Foreshortened as seen from `Camera::distance`
+    Perspective,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    // Point the camera orbits around, drawn at the origin of the screen
+    pub target: Vector3<f32>,
+    // Orbit around the vertical axis through `target`
+    pub yaw: Deg<f32>,
+    // Elevation above the horizontal plane, limited to just short of ±90°
+    pub pitch: Deg<f32>,
+    // From `target`, only matters for perspective projection
+    pub distance: f32,
+    // Magnification at the distance of `target`
+    pub zoom: f32,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            target: Vector3::new(0.0, 0.0, 0.0),
+            yaw: Deg(0.0),
+            pitch: Deg(0.0),
+            distance: 1000.0,
+            zoom: 1.0,
+            projection: Projection::Orthographic,
+        }
+    }
+}
+
+// Keeps the view direction away from the vertical, where the up vector is lost
+const MAX_PITCH: f32 = 89.0;
+// Points closer to the eye than this fraction of `distance` are left out
+const NEAR: f32 = 0.01;
+
+impl Camera {
+    // Turns by `yaw` and `pitch`, e.g. while dragging the mouse
+    pub fn orbit(&mut self, yaw: Deg<f32>, pitch: Deg<f32>) {
+        self.yaw = Deg((self.yaw + yaw).0.rem_euclid(360.0));
+        self.pitch = Deg((self.pitch + pitch).0.clamp(-MAX_PITCH, MAX_PITCH));
+    }
+
+    // Right, up and forward vectors of the view
+    fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let yaw = self.yaw.0.to_radians();
+        let pitch = self.pitch.0.clamp(-MAX_PITCH, MAX_PITCH).to_radians();
+        // From the eye towards `target`
+        let forward = -Vector3::new(pitch.cos() * yaw.sin(), pitch.sin(), pitch.cos() * yaw.cos());
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+        (right, up, forward)
+    }
+
+    // Screen position of `point` in x and y, z grows towards the viewer. Also
+    // returns how much lengths at `point` are magnified, or `None` for points
+    // behind the camera.
+    pub fn project_point(&self, point: Vector3<f32>) -> Option<(Vector3<f32>, f32)> {
+        let (right, up, forward) = self.basis();
+        let relative = point - self.target;
+        let depth = relative.dot(forward);
+        let scale = match self.projection {
+            Projection::Orthographic => self.zoom,
+            Projection::Perspective => {
+                let from_eye = self.distance + depth;
+                if from_eye < NEAR * self.distance {
+                    return None;
+                }
+                self.zoom * self.distance / from_eye
+            }
+        };
+        let screen = Vector3::new(relative.dot(right) * scale, relative.dot(up) * scale, -depth);
+        Some((screen, scale))
+    }
+
+    // The view of `geometry` projected onto the xy plane, anything reaching
+    // behind the camera is left out. `Geometry::elements` sorts the result back
+    // to front.
+    pub fn project(&self, geometry: &Geometry) -> Geometry {
+        let mut projected = Geometry {
+            branches: geometry.branches.clone(),
+            ..Geometry::default()
+        };
+
+        for segment in geometry.segments.iter() {
+            if let (Some((start, start_scale)), Some((end, end_scale))) =
+                (self.project_point(segment.start), self.project_point(segment.end))
+            {
+                projected.segments.push(Segment {
+                    start,
+                    end,
+                    thickness: segment.thickness * (start_scale + end_scale) / 2.0,
+                    ..*segment
+                });
+            }
+        }
+        for dot in geometry.dots.iter() {
+            if let Some((position, scale)) = self.project_point(dot.position) {
+                projected.dots.push(Dot {
+                    position,
+                    radius: dot.radius * scale,
+                    ..*dot
+                });
+            }
+        }
+        for polygon in geometry.polygons.iter() {
+            let vertices: Option<Vec<Vector3<f32>>> = polygon
+                .vertices
+                .iter()
+                .map(|&vertex| self.project_point(vertex).map(|(position, _)| position))
+                .collect();
+            if let Some(vertices) = vertices {
+                projected.polygons.push(Polygon { vertices, color: polygon.color, branch: polygon.branch });
+            }
+        }
+        projected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turtle::Element;
+    use palette::named::{BLACK, RED};
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn orbit_wraps_yaw_and_clamps_pitch() {
+        let mut camera = Camera::default();
+        camera.orbit(Deg(-30.0), Deg(60.0));
+        camera.orbit(Deg(400.0), Deg(60.0));
+        assert!((camera.yaw.0 - 10.0).abs() < 1e-4);
+        assert_eq!(camera.pitch, Deg(MAX_PITCH));
+        camera.orbit(Deg(0.0), Deg(-360.0));
+        assert_eq!(camera.pitch, Deg(-MAX_PITCH));
+    }
+
+    #[test]
+    fn orthographic_projection_keeps_sizes() {
+        let mut camera = Camera { zoom: 2.0, ..Camera::default() };
+        let (screen, scale) = camera.project_point(Vector3::new(1.0, 2.0, 3.0)).unwrap();
+        assert_near(screen, Vector3::new(2.0, 4.0, 3.0));
+        assert_eq!(scale, 2.0);
+
+        // Seen from the side the z axis points left, x towards the viewer
+        camera.orbit(Deg(90.0), Deg(0.0));
+        let (screen, _) = camera.project_point(Vector3::new(1.0, 0.0, 1.0)).unwrap();
+        assert_near(screen, Vector3::new(-2.0, 0.0, 1.0));
+        // Nothing is behind an orthographic camera
+        assert!(camera.project_point(Vector3::new(1e6, 0.0, 0.0)).is_some());
+    }
+
+    #[test]
+    fn perspective_projection_magnifies_near_points() {
+        let camera = Camera { projection: Projection::Perspective, ..Camera::default() };
+        let (screen, scale) = camera.project_point(Vector3::new(10.0, 0.0, 0.0)).unwrap();
+        assert_near(screen, Vector3::new(10.0, 0.0, 0.0));
+        assert_eq!(scale, 1.0);
+
+        // Halfway to the eye everything appears twice as large
+        let (screen, scale) = camera.project_point(Vector3::new(10.0, 0.0, 500.0)).unwrap();
+        assert_near(screen, Vector3::new(20.0, 0.0, 500.0));
+        assert!((scale - 2.0).abs() < 1e-4);
+
+        assert_eq!(camera.project_point(Vector3::new(0.0, 0.0, 1000.0)), None);
+        assert_eq!(camera.project_point(Vector3::new(0.0, 0.0, 2000.0)), None);
+    }
+
+    #[test]
+    fn projected_elements_are_drawn_back_to_front() {
+        let mut geometry = Geometry::default();
+        // A polygon behind a segment, which is behind a dot, recorded in the opposite order
+        geometry.dots.push(Dot { position: Vector3::new(0.0, 0.0, 1.0), radius: 1.0, color: RED, branch: 0 });
+        geometry.segments.push(Segment {
+            start: Vector3::new(0.0, 0.0, 0.0),
+            end: Vector3::new(1.0, 0.0, 0.0),
+            thickness: 1.0,
+            color: BLACK,
+            branch: 0,
+        });
+        geometry.polygons.push(Polygon {
+            vertices: vec![Vector3::new(0.0, 0.0, -1.0), Vector3::new(1.0, 0.0, -1.0), Vector3::new(0.0, 1.0, -1.0)],
+            color: BLACK,
+            branch: 0,
+        });
+        let kinds = |geometry: &Geometry| -> Vec<&str> {
+            geometry.elements().iter().map(|element| match element {
+                Element::Polygon(_) => "polygon",
+                Element::Segment(_) => "segment",
+                Element::Dot(_) => "dot",
+            }).collect()
+        };
+        assert_eq!(kinds(&Camera::default().project(&geometry)), ["polygon", "segment", "dot"]);
+
+        // From behind the order turns around
+        let behind = Camera { yaw: Deg(180.0), ..Camera::default() };
+        assert_eq!(kinds(&behind.project(&geometry)), ["dot", "segment", "polygon"]);
+
+        // Elements reaching behind a perspective camera are left out
+        let close = Camera { projection: Projection::Perspective, distance: 0.5, ..Camera::default() };
+        assert_eq!(kinds(&close.project(&geometry)), ["polygon", "segment"]);
+    }
+}
@@ -0,0 +1,72 @@
+use super::{Turtle, TurtleInterpreter};
+use crate::l_system::{Alphabet, Module, Symbol};
+
+// Open L-systems, see Měch and Prusinkiewicz, "Visual models of plants
+// interacting with their environment" (1996).
+//
+// Query modules such as `?E(x)` are named modules starting with `?`. Between
+// two derivation steps the word is interpreted by a turtle and the environment
+// fills in the parameters of every query module from the turtle state at that
+// point. The rules of the next step then see the new values:
+//
+//     ?E(x) : x>0 -> F[+F?E(1)][-F?E(1)]
+
+pub trait Environment {
+    // Writes the response to the query module `symbol` into `params`
+    fn query(&mut self, symbol: Symbol, turtle: &Turtle, params: &mut [f32]);
+}
+
+impl<F: FnMut(Symbol, &Turtle, &mut [f32])> Environment for F {
+    fn query(&mut self, symbol: Symbol, turtle: &Turtle, params: &mut [f32]) {
+        self(symbol, turtle, params)
+    }
+}
+
+// Walks `modules` with `turtle`, which moves according to the commands of
+// `interpreter`, and lets `environment` answer every query module
+pub fn query_environment(
+    modules: &mut [Module],
+    alphabet: &Alphabet,
+    interpreter: &TurtleInterpreter,
+    mut turtle: Turtle,
+    environment: &mut impl Environment,
+) {
+    for module in modules.iter_mut() {
+        if alphabet.is_query(module.symbol) {
+            environment.query(module.symbol, &turtle, &mut module.params);
+        } else {
+            interpreter.execute(module.symbol, &mut turtle, &module.params);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::parse_modules;
+
+    #[test]
+    fn queries_are_answered_from_the_turtle_state() {
+        let mut alphabet = Alphabet::default();
+        let query = alphabet.define("?E");
+        let mut modules = parse_modules("F?E(0)F[?E(0,0)]F(3)", &alphabet).unwrap();
+
+        // Height of the turtle, and how deep it is in the stack if asked
+        let mut queried = Vec::new();
+        let mut environment = |symbol: Symbol, turtle: &Turtle, params: &mut [f32]| {
+            queried.push(symbol);
+            params[0] = turtle.position.y;
+            if let Some(depth) = params.get_mut(1) {
+                *depth = turtle.stack.len() as f32;
+            }
+        };
+        let interpreter = TurtleInterpreter::abop();
+        query_environment(&mut modules, &alphabet, &interpreter, Turtle::default(), &mut environment);
+
+        assert_eq!(queried, vec![query, query]);
+        assert_eq!(modules[1].params, vec![1.0]);
+        assert_eq!(modules[4].params, vec![2.0, 1.0]);
+        // Other modules keep their parameters
+        assert_eq!(modules[6].params, vec![3.0]);
+    }
+}
@@ -0,0 +1,178 @@
+use cgmath::Vector3;
+
+use crate::l_system::Rgb8;
+
+// Everything a turtle produces, independent of how it is displayed.
+// Drawing with nannou is just one consumer, see `draw.rs` of the viewer examples.
+//
+// The branch hierarchy follows the turtle stack: the root branch is 0 and
+// every push starts a new branch whose parent is the branch the turtle was
+// on. Segments, dots and polygons refer to the branch they belong to.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub start: Vector3<f32>,
+    pub end: Vector3<f32>,
+    pub thickness: f32,
+    pub color: Rgb8,
+    pub branch: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dot {
+    pub position: Vector3<f32>,
+    pub radius: f32,
+    pub color: Rgb8,
+    pub branch: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon {
+    pub vertices: Vec<Vector3<f32>>,
+    pub color: Rgb8,
+    pub branch: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Branch {
+    // `None` for the root
+    pub parent: Option<usize>,
+    // Depth of the turtle stack, 0 for the root
+    pub depth: usize,
+}
+
+// Any one of the drawn elements of a `Geometry`, see `Geometry::elements`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Element<'a> {
+    Polygon(&'a Polygon),
+    Segment(&'a Segment),
+    Dot(&'a Dot),
+}
+
+impl Element<'_> {
+    // Mean z, which grows towards the viewer once projected by a `Camera`
+    pub fn depth(&self) -> f32 {
+        match self {
+            Element::Polygon(polygon) => {
+                polygon.vertices.iter().map(|v| v.z).sum::<f32>() / polygon.vertices.len().max(1) as f32
+            }
+            Element::Segment(segment) => (segment.start.z + segment.end.z) / 2.0,
+            Element::Dot(dot) => dot.position.z,
+        }
+    }
+
+    pub fn branch(&self) -> usize {
+        match self {
+            Element::Polygon(polygon) => polygon.branch,
+            Element::Segment(segment) => segment.branch,
+            Element::Dot(dot) => dot.branch,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Geometry {
+    pub segments: Vec<Segment>,
+    pub dots: Vec<Dot>,
+    pub polygons: Vec<Polygon>,
+    pub branches: Vec<Branch>,
+}
+
+impl Default for Geometry {
+    fn default() -> Geometry {
+        Geometry {
+            segments: Vec::new(),
+            dots: Vec::new(),
+            polygons: Vec::new(),
+            branches: vec![Branch { parent: None, depth: 0 }],
+        }
+    }
+}
+
+impl Geometry {
+    // Starts a child branch of `parent` and returns its id
+    pub fn branch(&mut self, parent: usize) -> usize {
+        let depth = self.branches[parent].depth + 1;
+        self.branches.push(Branch { parent: Some(parent), depth });
+        self.branches.len() - 1
+    }
+
+    pub fn depth(&self, branch: usize) -> usize {
+        self.branches[branch].depth
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty() && self.dots.is_empty() && self.polygons.is_empty()
+    }
+
+    // Every element back to front, whatever its kind, so nearer ones are drawn
+    // over farther ones. At the same depth polygons come first and dots last.
+    pub fn elements(&self) -> Vec<Element<'_>> {
+        let mut elements: Vec<Element> = self
+            .polygons
+            .iter()
+            .map(Element::Polygon)
+            .chain(self.segments.iter().map(Element::Segment))
+            .chain(self.dots.iter().map(Element::Dot))
+            .collect();
+        elements.sort_by(|a, b| a.depth().total_cmp(&b.depth()));
+        elements
+    }
+
+    // Smallest and largest corner of everything drawn, including line widths and
+    // dot radii. `None` if nothing was drawn.
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let mut points = Vec::new();
+        for segment in self.segments.iter() {
+            points.push((segment.start, segment.thickness / 2.0));
+            points.push((segment.end, segment.thickness / 2.0));
+        }
+        for dot in self.dots.iter() {
+            points.push((dot.position, dot.radius));
+        }
+        for polygon in self.polygons.iter() {
+            points.extend(polygon.vertices.iter().map(|&vertex| (vertex, 0.0)));
+        }
+
+        let mut bounds = None;
+        for (point, extent) in points {
+            let extent = Vector3::new(extent, extent, extent);
+            let (low, high) = (point - extent, point + extent);
+            bounds = Some(match bounds {
+                Some((min, max)) => (component_min(min, low), component_max(max, high)),
+                None => (low, high),
+            });
+        }
+        bounds
+    }
+}
+
+fn component_min(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn component_max(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::named::BLACK;
+
+    #[test]
+    fn bounds_include_line_widths_and_dot_radii() {
+        let mut geometry = Geometry::default();
+        assert_eq!(geometry.bounds(), None);
+
+        geometry.segments.push(Segment {
+            start: Vector3::new(0.0, 0.0, 0.0),
+            end: Vector3::new(0.0, 4.0, 0.0),
+            thickness: 2.0,
+            color: BLACK,
+            branch: 0,
+        });
+        geometry.dots.push(Dot { position: Vector3::new(3.0, 0.0, 0.0), radius: 0.5, color: BLACK, branch: 0 });
+        assert_eq!(geometry.bounds(), Some((Vector3::new(-1.0, -1.0, -1.0), Vector3::new(3.5, 5.0, 1.0))));
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use cgmath::Deg;
+
+use super::{Geometry, Turtle};
+use crate::l_system::{Module, Symbol};
+
+// Turns the symbols of a derived word into turtle actions. Commands are
+// registered per symbol, symbols without a command are ignored, so modules
+// that only control the evolution of the word, like `X`, need no entry.
+//
+// Commands get the parameters of parametric modules, which are empty for
+// plain symbols. The default table follows ABOP, where a first parameter
+// overrides the line length or turning angle:
+//
+//     F(l)  move forward by l drawing a line
+//     f(l)  move forward by l without drawing a line
+//     G(l)  same as f, used to outline polygons
+//     +(a)  turn left by a            -(a)  turn right by a
+//     ^(a)  pitch up by a             &(a)  pitch down by a
+//     /(a)  roll left by a            \(a)  roll right by a
+//     |     turn around
+//     $     roll until the left vector is horizontal
+//     [     push the turtle state     ]     pop the turtle state
+//     #     double the line width     !     halve the line width
+//     @     draw a dot with line width radius
+//     {     open a polygon            }     close and fill it with the fill colour
+//     .     record a polygon vertex
+//     '     use the next colour of the palette
+//     ;(n)  use colour n of the palette, the next one without a parameter
+//     (     decrease the turning angle by 5°
+//     )     increase the turning angle by 5°
+//     >     multiply the line length by 1.5
+//     <     divide the line length by 1.5
+//     °     multiply the line length by 0.9
+//
+// Grammars written for the former meaning of `&` can use `abop_legacy`.
+
+type Command = Box<dyn Fn(&mut Turtle, &[f32])>;
+
+pub struct TurtleInterpreter {
+    commands: HashMap<Symbol, Command>,
+}
+
+impl Default for TurtleInterpreter {
+    fn default() -> TurtleInterpreter {
+        TurtleInterpreter::abop()
+    }
+}
+
+impl TurtleInterpreter {
+    // An interpreter without any commands
+    pub fn empty() -> TurtleInterpreter {
+        TurtleInterpreter { commands: HashMap::new() }
+    }
+
+    // The standard ABOP command table, see above
+    pub fn abop() -> TurtleInterpreter {
+        let turning_angle_increment = Deg(5.0);
+        let line_length_scaling_factor = 1.5;
+
+        fn length(turtle: &Turtle, params: &[f32]) -> f32 {
+            params.first().copied().unwrap_or(turtle.line_length)
+        }
+        fn angle(turtle: &Turtle, params: &[f32]) -> Deg<f32> {
+            params.first().copied().map(Deg).unwrap_or(turtle.turn_angle)
+        }
+
+        let mut interpreter = TurtleInterpreter::empty();
+        interpreter.register('F', |turtle, params| turtle.forward(length(turtle, params)));
+        interpreter.register('f', |turtle, params| turtle.forward_no_draw(length(turtle, params)));
+        interpreter.register('G', |turtle, params| turtle.forward_no_draw(length(turtle, params)));
+        interpreter.register('+', |turtle, params| turtle.yaw(-angle(turtle, params)));
+        interpreter.register('-', |turtle, params| turtle.yaw(angle(turtle, params)));
+        interpreter.register('|', |turtle, _| turtle.turn_around());
+        interpreter.register('^', |turtle, params| turtle.pitch(angle(turtle, params)));
+        interpreter.register('&', |turtle, params| turtle.pitch(-angle(turtle, params)));
+        interpreter.register('/', |turtle, params| turtle.roll(angle(turtle, params)));
+        interpreter.register('\\', |turtle, params| turtle.roll(-angle(turtle, params)));
+        interpreter.register('$', |turtle, _| turtle.rotate_to_vertical());
+        interpreter.register('[', |turtle, _| turtle.push());
+        interpreter.register(']', |turtle, _| turtle.pop());
+        interpreter.register('#', |turtle, _| turtle.increment_thickness(turtle.thickness));
+        // Halving undoes `#` and never reaches zero width
+        interpreter.register('!', |turtle, _| turtle.decrement_thickness(turtle.thickness / 2.0));
+        interpreter.register('@', |turtle, _| turtle.dot(turtle.thickness));
+        interpreter.register('{', |turtle, _| turtle.begin_polygon());
+        interpreter.register('.', |turtle, _| turtle.vertex());
+        interpreter.register('}', |turtle, _| turtle.end_polygon());
+        interpreter.register('\'', |turtle, _| turtle.color_index += 1);
+        interpreter.register(';', |turtle, params| match params.first() {
+            Some(&index) => turtle.color_index = index.max(0.0) as usize,
+            None => turtle.color_index += 1,
+        });
+        interpreter.register('(', move |turtle, _| turtle.turn_angle -= turning_angle_increment);
+        interpreter.register(')', move |turtle, _| turtle.turn_angle += turning_angle_increment);
+        interpreter.register('>', move |turtle, _| turtle.line_length *= line_length_scaling_factor);
+        interpreter.register('<', move |turtle, _| turtle.line_length /= line_length_scaling_factor);
+        interpreter.register('°', |turtle, _| turtle.line_length *= 0.9);
+        interpreter
+    }
+
+    // The ABOP table, except that `&` reverses the direction of every turn,
+    // pitch and roll like it used to
+    pub fn abop_legacy() -> TurtleInterpreter {
+        let mut interpreter = TurtleInterpreter::abop();
+        interpreter.register('&', |turtle, _| turtle.reverse_turn());
+        interpreter
+    }
+
+    // Replaces the command of `symbol` if it already has one
+    pub fn register(
+        &mut self,
+        symbol: impl Into<Symbol>,
+        command: impl Fn(&mut Turtle, &[f32]) + 'static,
+    ) {
+        self.commands.insert(symbol.into(), Box::new(command));
+    }
+
+    pub fn remove(&mut self, symbol: impl Into<Symbol>) {
+        self.commands.remove(&symbol.into());
+    }
+
+    pub fn execute(&self, symbol: Symbol, turtle: &mut Turtle, params: &[f32]) {
+        if let Some(command) = self.commands.get(&symbol) {
+            command(turtle, params);
+        }
+    }
+
+    // Returns the geometry `turtle` recorded while walking `word`
+    pub fn interpret(&self, mut turtle: Turtle, word: impl IntoIterator<Item = Symbol>) -> Geometry {
+        for symbol in word {
+            self.execute(symbol, &mut turtle, &[]);
+        }
+        turtle.geometry
+    }
+
+    pub fn interpret_modules(&self, mut turtle: Turtle, modules: &[Module]) -> Geometry {
+        for module in modules {
+            self.execute(module.symbol, &mut turtle, &module.params);
+        }
+        turtle.geometry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn width_commands_double_and_halve() {
+        let word = "F#F!F!F".chars().map(Symbol::from);
+        let geometry = TurtleInterpreter::abop().interpret(Turtle::default(), word);
+        let widths: Vec<f32> = geometry.segments.iter().map(|s| s.thickness).collect();
+        assert_eq!(widths, vec![2.0, 4.0, 2.0, 1.0]);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Quaternion, Rotation, Vector2, Vector3};
+
+use super::{Geometry, Segment};
+
+// Branches as generalised cylinders, see Bloomenthal, "Modeling the mighty
+// maple" (1985).
+//
+// Consecutive segments of a branch form one tube with a ring of vertices at
+// every joint. The rings are oriented with parallel transport frames, which
+// rotate each ring as little as possible from the one before, so tubes don't
+// twist around bends. The radius is half the line width and runs from one
+// segment's width to the next, so `#` and `!` taper the tube. The ends of
+// tubes are left open.
+
+// Triangles with per vertex normals and texture coordinates
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    // u runs around the tube, v along it, one unit per circumference
+    pub uvs: Vec<Vector2<f32>>,
+    // Counterclockwise seen from outside
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    // Adds the vertices and triangles of `other`
+    pub fn append(&mut self, other: &Mesh) {
+        let offset = self.positions.len();
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.triangles.extend(
+            other.triangles.iter().map(|t| [t[0] + offset, t[1] + offset, t[2] + offset]),
+        );
+    }
+}
+
+// Segments shorter than this are skipped, they have no direction
+const MIN_LENGTH: f32 = 1e-6;
+
+// One tube around every chain of connected segments, with `sides` vertices per ring
+pub fn branch_mesh(geometry: &Geometry, sides: usize) -> Mesh {
+    let sides = sides.max(3);
+    let mut mesh = Mesh::default();
+    for chain in chains(geometry) {
+        tube(&chain, sides, &mut mesh);
+    }
+    mesh
+}
+
+// Splits the segments into runs where each one starts where the previous one
+// of the same branch ended
+fn chains(geometry: &Geometry) -> Vec<Vec<&Segment>> {
+    let mut chains: Vec<Vec<&Segment>> = Vec::new();
+    // The chain each branch is currently drawing
+    let mut open: HashMap<usize, usize> = HashMap::new();
+
+    for segment in geometry.segments.iter() {
+        if (segment.end - segment.start).magnitude() < MIN_LENGTH {
+            continue;
+        }
+        let continues = open.get(&segment.branch).filter(|&&chain| {
+            let last = chains[chain].last().unwrap();
+            (segment.start - last.end).magnitude() < MIN_LENGTH
+        });
+        match continues {
+            Some(&chain) => chains[chain].push(segment),
+            None => {
+                open.insert(segment.branch, chains.len());
+                chains.push(vec![segment]);
+            }
+        }
+    }
+    chains
+}
+
+fn tube(chain: &[&Segment], sides: usize, mesh: &mut Mesh) {
+    let direction = |segment: &Segment| (segment.end - segment.start).normalize();
+
+    // Centre, tangent and radius of every ring: the start of the chain, every
+    // joint and its end. Joints face halfway between both segments.
+    let first = chain[0];
+    let mut rings = vec![(first.start, direction(first), first.thickness / 2.0)];
+    for pair in chain.windows(2) {
+        let tangent = direction(pair[0]) + direction(pair[1]);
+        let tangent = if tangent.magnitude() < MIN_LENGTH { direction(pair[1]) } else { tangent.normalize() };
+        rings.push((pair[0].end, tangent, pair[0].thickness / 2.0));
+    }
+    let last = chain[chain.len() - 1];
+    rings.push((last.end, direction(last), last.thickness / 2.0));
+
+    let circumference = (2.0 * PI * rings[0].2).max(MIN_LENGTH);
+    let mut normal = perpendicular(rings[0].1);
+    let mut previous_tangent = rings[0].1;
+    let mut v = 0.0;
+    let base = mesh.positions.len();
+
+    for (i, &(centre, tangent, radius)) in rings.iter().enumerate() {
+        // Parallel transport: rotate the frame by the rotation between tangents
+        let rotation = Quaternion::between_vectors(previous_tangent, tangent);
+        normal = rotation.rotate_vector(normal);
+        // Keep it exactly perpendicular against rounding errors
+        normal = (normal - tangent * normal.dot(tangent)).normalize();
+        let binormal = tangent.cross(normal);
+        previous_tangent = tangent;
+        if i > 0 {
+            v += (centre - rings[i - 1].0).magnitude() / circumference;
+        }
+
+        // The first vertex is repeated at the end for the texture seam
+        for side in 0..=sides {
+            let angle = 2.0 * PI * side as f32 / sides as f32;
+            let radial = normal * angle.cos() + binormal * angle.sin();
+            mesh.positions.push(centre + radial * radius);
+            mesh.normals.push(radial);
+            mesh.uvs.push(Vector2::new(side as f32 / sides as f32, v));
+        }
+    }
+
+    let stride = sides + 1;
+    for ring in 0..rings.len() - 1 {
+        for side in 0..sides {
+            let a = base + ring * stride + side;
+            let (b, c, d) = (a + 1, a + stride, a + stride + 1);
+            mesh.triangles.push([a, b, d]);
+            mesh.triangles.push([a, d, c]);
+        }
+    }
+}
+
+// Any unit vector perpendicular to `v`
+fn perpendicular(v: Vector3<f32>) -> Vector3<f32> {
+    let helper = if v.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    v.cross(helper).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::Symbol;
+    use crate::turtle::{Turtle, TurtleInterpreter};
+
+    // Radius of every ring of a tube along the y axis
+    fn ring_radii(mesh: &Mesh, sides: usize) -> Vec<f32> {
+        mesh.positions
+            .chunks(sides + 1)
+            .map(|ring| (ring[0].x * ring[0].x + ring[0].z * ring[0].z).sqrt())
+            .collect()
+    }
+
+    #[test]
+    fn width_commands_taper_tubes() {
+        let word = "F#F!F!F".chars().map(Symbol::from);
+        let geometry = TurtleInterpreter::abop().interpret(Turtle::default(), word);
+        let mesh = branch_mesh(&geometry, 4);
+
+        assert_eq!(mesh.triangles.len(), 4 * 4 * 2);
+        let radii = ring_radii(&mesh, 4);
+        let expected = [1.0, 1.0, 2.0, 1.0, 0.5];
+        assert_eq!(radii.len(), expected.len());
+        for (radius, expected) in radii.iter().zip(expected.iter()) {
+            assert!((radius - expected).abs() < 1e-5, "{:?}", radii);
+        }
+    }
+
+    #[test]
+    fn branches_get_tubes_of_their_own() {
+        let word = "F[+F]F".chars().map(Symbol::from);
+        let geometry = TurtleInterpreter::abop().interpret(Turtle::default(), word);
+        let mesh = branch_mesh(&geometry, 3);
+
+        // The trunk has three rings, the side branch two
+        assert_eq!(mesh.positions.len(), (3 + 2) * 4);
+        assert_eq!(mesh.triangles.len(), (2 + 1) * 3 * 2);
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector3};
+
+use super::{Geometry, branch_mesh};
+use crate::l_system::Rgb8;
+
+// Wavefront OBJ export for 3D packages like Blender. Branches become
+// generalised cylinders, see `branch_mesh`, in the group `bark`. Leaf polygons
+// become faces of their own in the group `foliage`. Each group has a material
+// of the same name in the accompanying MTL file, coloured with the mean colour
+// of its elements. Coordinates are written as recorded, with y up.
+
+// Vertices around each branch
+const SIDES: usize = 8;
+
+// Mean colour as MTL `Kd` values, grey if there is nothing to average
+fn diffuse(colors: impl Iterator<Item = Rgb8>) -> (f32, f32, f32) {
+    let (mut sum, mut count) = ([0.0; 3], 0);
+    for color in colors {
+        sum[0] += color.red as f32;
+        sum[1] += color.green as f32;
+        sum[2] += color.blue as f32;
+        count += 1;
+    }
+    if count == 0 {
+        return (0.5, 0.5, 0.5);
+    }
+    let mean = |channel: f32| channel / count as f32 / 255.0;
+    (mean(sum[0]), mean(sum[1]), mean(sum[2]))
+}
+
+// Unit normal of a planar polygon by Newell's method
+fn polygon_normal(vertices: &[Vector3<f32>]) -> Vector3<f32> {
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    for (i, a) in vertices.iter().enumerate() {
+        let b = vertices[(i + 1) % vertices.len()];
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+    }
+    if normal.magnitude() < f32::EPSILON {
+        Vector3::unit_z()
+    } else {
+        normal.normalize()
+    }
+}
+
+// The OBJ file, which refers to the materials in `mtl_name`, and the MTL file
+pub fn to_obj(geometry: &Geometry, mtl_name: &str) -> (String, String) {
+    let mut obj = String::new();
+    writeln!(obj, "mtllib {}", mtl_name).unwrap();
+
+    let bark = branch_mesh(geometry, SIDES);
+    if !bark.is_empty() {
+        writeln!(obj, "o bark\ng bark\nusemtl bark").unwrap();
+        for p in bark.positions.iter() {
+            writeln!(obj, "v {:.4} {:.4} {:.4}", p.x, p.y, p.z).unwrap();
+        }
+        for n in bark.normals.iter() {
+            writeln!(obj, "vn {:.4} {:.4} {:.4}", n.x, n.y, n.z).unwrap();
+        }
+        for uv in bark.uvs.iter() {
+            writeln!(obj, "vt {:.4} {:.4}", uv.x, uv.y).unwrap();
+        }
+        // OBJ indices start at 1 and are shared by position, uv and normal
+        for triangle in bark.triangles.iter() {
+            let [a, b, c] = triangle.map(|i| i + 1);
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}", a = a, b = b, c = c).unwrap();
+        }
+    }
+
+    if !geometry.polygons.is_empty() {
+        writeln!(obj, "o foliage\ng foliage\nusemtl foliage").unwrap();
+        let (mut vertex, mut normal) = (bark.positions.len(), bark.normals.len());
+        for polygon in geometry.polygons.iter() {
+            for v in polygon.vertices.iter() {
+                writeln!(obj, "v {:.4} {:.4} {:.4}", v.x, v.y, v.z).unwrap();
+            }
+            let n = polygon_normal(&polygon.vertices);
+            writeln!(obj, "vn {:.4} {:.4} {:.4}", n.x, n.y, n.z).unwrap();
+            normal += 1;
+
+            let corners: Vec<String> = (1..=polygon.vertices.len())
+                .map(|i| format!("{}//{}", vertex + i, normal))
+                .collect();
+            writeln!(obj, "f {}", corners.join(" ")).unwrap();
+            vertex += polygon.vertices.len();
+        }
+    }
+
+    let mut mtl = String::new();
+    let materials = [
+        ("bark", diffuse(geometry.segments.iter().map(|s| s.color))),
+        ("foliage", diffuse(geometry.polygons.iter().map(|p| p.color))),
+    ];
+    for (name, (r, g, b)) in materials.iter() {
+        writeln!(mtl, "newmtl {}\nKd {:.4} {:.4} {:.4}\nKa 0 0 0\nKs 0 0 0\nd 1\nillum 1\n", name, r, g, b).unwrap();
+    }
+    (obj, mtl)
+}
+
+// Writes `path` and an MTL file next to it with the same name. Creates missing
+// parent directories.
+pub fn write_obj(geometry: &Geometry, path: &Path) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path.file_name().and_then(|name| name.to_str()).unwrap_or("materials.mtl");
+    let (obj, mtl) = to_obj(geometry, mtl_name);
+    std::fs::write(path, obj)?;
+    std::fs::write(&mtl_path, mtl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::Symbol;
+    use crate::turtle::{Turtle, TurtleInterpreter};
+
+    fn count(obj: &str, keyword: &str) -> usize {
+        obj.lines().filter(|line| line.split_whitespace().next() == Some(keyword)).count()
+    }
+
+    #[test]
+    fn branches_and_leaves_go_into_their_own_groups() {
+        let word = "FF[{.f.+f.}]".chars().map(Symbol::from);
+        let geometry = TurtleInterpreter::abop().interpret(Turtle::default(), word);
+        let (obj, mtl) = to_obj(&geometry, "tree.mtl");
+
+        // One tube of three rings with a repeated seam vertex, two triangles per side and segment
+        let rings = 3 * (SIDES + 1);
+        assert_eq!(count(&obj, "v"), rings + 3);
+        assert_eq!(count(&obj, "vn"), rings + 1);
+        assert_eq!(count(&obj, "vt"), rings);
+        assert_eq!(count(&obj, "f"), 2 * 2 * SIDES + 1);
+
+        let bark = obj.find("g bark\nusemtl bark").unwrap();
+        let foliage = obj.find("g foliage\nusemtl foliage").unwrap();
+        assert!(bark < foliage);
+        // The leaf refers to its own vertices and normal, after those of the bark
+        assert_eq!(obj[foliage..].lines().find(|line| line.starts_with("f ")), Some("f 28//28 29//28 30//28"));
+
+        assert!(obj.starts_with("mtllib tree.mtl\n"));
+        assert!(mtl.contains("newmtl bark\nKd 0.1333 0.5451 0.1333\n"));
+        assert!(mtl.contains("newmtl foliage\n"));
+    }
+}
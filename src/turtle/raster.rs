@@ -0,0 +1,235 @@
+use std::path::Path;
+
+use image::{Rgba, RgbaImage};
+use cgmath::Vector3;
+
+use super::{Element, Geometry};
+use crate::l_system::Rgb8;
+
+// Software rendering of turtle geometry into an RGBA buffer, so drawings can
+// be rendered on machines without a window or GPU.
+//
+// Like `Geometry::draw` this renders the projection onto the xy plane back to
+// front, see `Geometry::elements`. The drawing is scaled to fit the
+// image, line widths and dot radii are scaled along with it. Segments get
+// round caps, which keeps the joints of consecutive segments closed.
+
+// Space around the drawing, in pixels
+const MARGIN: f32 = 10.0;
+// Sub-scanlines per pixel row when filling polygons
+const SUBSAMPLES: usize = 4;
+
+// Maps drawing coordinates to pixel coordinates
+struct Transform {
+    scale: f32,
+    offset_x: f32,
+    offset_y: f32,
+}
+
+impl Transform {
+    fn fit(geometry: &Geometry, width: u32, height: u32) -> Transform {
+        let (min, max) = match geometry.bounds() {
+            Some(bounds) => bounds,
+            None => return Transform { scale: 1.0, offset_x: 0.0, offset_y: 0.0 },
+        };
+        let available_width = (width as f32 - 2.0 * MARGIN).max(1.0);
+        let available_height = (height as f32 - 2.0 * MARGIN).max(1.0);
+        let scale = (available_width / (max.x - min.x).max(f32::EPSILON))
+            .min(available_height / (max.y - min.y).max(f32::EPSILON));
+
+        // Centred, with y pointing down
+        Transform {
+            scale,
+            offset_x: (width as f32 - (max.x - min.x) * scale) / 2.0 - min.x * scale,
+            offset_y: (height as f32 - (max.y - min.y) * scale) / 2.0 + max.y * scale,
+        }
+    }
+
+    fn apply(&self, point: Vector3<f32>) -> (f32, f32) {
+        (
+            point.x * self.scale + self.offset_x,
+            self.offset_y - point.y * self.scale,
+        )
+    }
+}
+
+struct Canvas {
+    image: RgbaImage,
+}
+
+impl Canvas {
+    // Blends `color` over the pixel with `coverage` between 0.0 and 1.0
+    fn blend(&mut self, x: i64, y: i64, color: Rgb8, coverage: f32) {
+        if coverage <= 0.0 || x < 0 || y < 0 || x >= self.image.width() as i64 || y >= self.image.height() as i64 {
+            return;
+        }
+        let alpha = coverage.min(1.0);
+        let pixel = self.image.get_pixel_mut(x as u32, y as u32);
+        let source = [color.red, color.green, color.blue];
+        for (destination, &source) in pixel.0.iter_mut().zip(source.iter()) {
+            *destination = (source as f32 * alpha + *destination as f32 * (1.0 - alpha)).round() as u8;
+        }
+        let destination_alpha = pixel.0[3] as f32 / 255.0;
+        pixel.0[3] = ((alpha + destination_alpha * (1.0 - alpha)) * 255.0).round() as u8;
+    }
+
+    // Pixels within the box around a shape, clamped to the image
+    fn pixels_around(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> impl Iterator<Item = (i64, i64)> {
+        let x0 = (min_x.floor() as i64).max(0);
+        let y0 = (min_y.floor() as i64).max(0);
+        let x1 = (max_x.ceil() as i64).min(self.image.width() as i64 - 1);
+        let y1 = (max_y.ceil() as i64).min(self.image.height() as i64 - 1);
+        (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| (x, y)))
+    }
+
+    // Anti-aliased line of `width` pixels with round caps
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), width: f32, color: Rgb8) {
+        let radius = width / 2.0;
+        let reach = radius + 1.0;
+        let pixels: Vec<(i64, i64)> = self
+            .pixels_around(
+                start.0.min(end.0) - reach,
+                start.1.min(end.1) - reach,
+                start.0.max(end.0) + reach,
+                start.1.max(end.1) + reach,
+            )
+            .collect();
+
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let length_squared = dx * dx + dy * dy;
+        for (x, y) in pixels {
+            // Distance from the pixel centre to the closest point on the line
+            let (px, py) = (x as f32 + 0.5 - start.0, y as f32 + 0.5 - start.1);
+            let t = if length_squared > 0.0 {
+                ((px * dx + py * dy) / length_squared).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let distance = ((px - t * dx).powi(2) + (py - t * dy).powi(2)).sqrt();
+            self.blend(x, y, color, radius + 0.5 - distance);
+        }
+    }
+
+    fn disc(&mut self, centre: (f32, f32), radius: f32, color: Rgb8) {
+        self.line(centre, centre, 2.0 * radius, color);
+    }
+
+    // Even-odd scanline fill, anti-aliased by sampling several sub-scanlines per row
+    fn polygon(&mut self, vertices: &[(f32, f32)], color: Rgb8) {
+        // A vertex at NaN or infinity leaves nothing sensible to fill
+        if vertices.len() < 3 || vertices.iter().any(|v| !v.0.is_finite() || !v.1.is_finite()) {
+            return;
+        }
+        let min_y = vertices.iter().map(|v| v.1).fold(f32::INFINITY, f32::min);
+        let max_y = vertices.iter().map(|v| v.1).fold(f32::NEG_INFINITY, f32::max);
+        let y0 = (min_y.floor() as i64).max(0);
+        let y1 = (max_y.ceil() as i64).min(self.image.height() as i64 - 1);
+        let width = self.image.width() as usize;
+
+        let mut coverage = vec![0.0; width + 1];
+        let mut crossings = Vec::new();
+        for y in y0..=y1 {
+            coverage.iter_mut().for_each(|c| *c = 0.0);
+            for sample in 0..SUBSAMPLES {
+                let scan_y = y as f32 + (sample as f32 + 0.5) / SUBSAMPLES as f32;
+                crossings.clear();
+                for (i, a) in vertices.iter().enumerate() {
+                    let b = vertices[(i + 1) % vertices.len()];
+                    if (a.1 <= scan_y) != (b.1 <= scan_y) {
+                        crossings.push(a.0 + (scan_y - a.1) / (b.1 - a.1) * (b.0 - a.0));
+                    }
+                }
+                crossings.sort_by(|a, b| a.total_cmp(b));
+                for span in crossings.chunks_exact(2) {
+                    add_span(&mut coverage, span[0], span[1], 1.0 / SUBSAMPLES as f32);
+                }
+            }
+            for (x, &c) in coverage.iter().take(width).enumerate() {
+                self.blend(x as i64, y, color, c);
+            }
+        }
+    }
+}
+
+// Adds `weight` times the horizontal overlap of [x0, x1] with each pixel
+fn add_span(coverage: &mut [f32], x0: f32, x1: f32, weight: f32) {
+    let x0 = x0.max(0.0);
+    let x1 = x1.min(coverage.len() as f32 - 1.0);
+    if x1 <= x0 {
+        return;
+    }
+    let (first, last) = (x0.floor() as usize, x1.floor() as usize);
+    if first == last {
+        coverage[first] += (x1 - x0) * weight;
+        return;
+    }
+    coverage[first] += (first as f32 + 1.0 - x0) * weight;
+    for c in coverage[first + 1..last].iter_mut() {
+        *c += weight;
+    }
+    coverage[last] += (x1 - last as f32) * weight;
+}
+
+// Renders `geometry` onto a white, opaque `width` x `height` image
+pub fn rasterize(geometry: &Geometry, width: u32, height: u32) -> RgbaImage {
+    let transform = Transform::fit(geometry, width, height);
+    let mut canvas = Canvas {
+        image: RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255])),
+    };
+
+    for element in geometry.elements() {
+        match element {
+            Element::Polygon(polygon) => {
+                let vertices: Vec<(f32, f32)> = polygon.vertices.iter().map(|&v| transform.apply(v)).collect();
+                canvas.polygon(&vertices, polygon.color);
+            }
+            Element::Segment(segment) => canvas.line(
+                transform.apply(segment.start),
+                transform.apply(segment.end),
+                segment.thickness * transform.scale,
+                segment.color,
+            ),
+            Element::Dot(dot) => {
+                canvas.disc(transform.apply(dot.position), dot.radius * transform.scale, dot.color)
+            }
+        }
+    }
+    canvas.image
+}
+
+// Creates missing parent directories
+pub fn write_png(geometry: &Geometry, width: u32, height: u32, path: &Path) -> image::ImageResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    rasterize(geometry, width, height).save_with_format(path, image::ImageFormat::Png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::Symbol;
+    use crate::turtle::{Turtle, TurtleInterpreter};
+    use palette::named::FORESTGREEN;
+
+    #[test]
+    fn draws_without_a_window() {
+        let word = "FF".chars().map(Symbol::from);
+        let geometry = TurtleInterpreter::abop().interpret(Turtle::default(), word);
+        let image = rasterize(&geometry, 64, 64);
+
+        // The trunk runs up the middle, fitted to the image
+        let green = FORESTGREEN;
+        assert_eq!(*image.get_pixel(32, 32), Rgba([green.red, green.green, green.blue, 255]));
+        assert_eq!(*image.get_pixel(32, 5), Rgba([255, 255, 255, 255]));
+        assert_eq!(*image.get_pixel(5, 32), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn polygons_with_nan_vertices_are_skipped() {
+        let white = RgbaImage::from_pixel(8, 8, Rgba([255, 255, 255, 255]));
+        let mut canvas = Canvas { image: white.clone() };
+        canvas.polygon(&[(1.0, 1.0), (f32::NAN, 6.0), (6.0, 6.0)], FORESTGREEN);
+        assert_eq!(canvas.image, white);
+    }
+}
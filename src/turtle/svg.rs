@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use super::{Element, Geometry};
+use crate::l_system::Rgb8;
+
+// Space around the drawing, in drawing units
+const MARGIN: f32 = 10.0;
+
+// SVG y points down. Subtracting from 0.0 avoids printing -0.000
+fn flip(y: f32) -> f32 {
+    0.0 - y
+}
+
+fn hex(color: Rgb8) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue)
+}
+
+// Renders the projection of `geometry` onto the xy plane. Everything drawn at
+// the same turtle stack depth goes into one `<g>` element, the viewBox is fitted
+// to the bounds of the drawing.
+pub fn to_svg(geometry: &Geometry) -> String {
+    let (x, y, width, height) = match geometry.bounds() {
+        Some((min, max)) => (
+            min.x - MARGIN,
+            flip(max.y) - MARGIN,
+            max.x - min.x + 2.0 * MARGIN,
+            max.y - min.y + 2.0 * MARGIN,
+        ),
+        None => (0.0, 0.0, 1.0, 1.0),
+    };
+
+    // The elements of every stack depth, back to front
+    let mut groups: BTreeMap<usize, String> = BTreeMap::new();
+    for element in geometry.elements() {
+        let group = groups.entry(geometry.depth(element.branch())).or_default();
+        match element {
+            Element::Polygon(polygon) => {
+                let points: Vec<String> = polygon
+                    .vertices
+                    .iter()
+                    .map(|v| format!("{:.3},{:.3}", v.x, flip(v.y)))
+                    .collect();
+                writeln!(
+                    group,
+                    r#"    <polygon points="{}" fill="{}"/>"#,
+                    points.join(" "),
+                    hex(polygon.color)
+                )
+            }
+            Element::Segment(segment) => writeln!(
+                group,
+                r#"    <line x1="{:.3}" y1="{:.3}" x2="{:.3}" y2="{:.3}" stroke="{}" stroke-width="{:.3}"/>"#,
+                segment.start.x,
+                flip(segment.start.y),
+                segment.end.x,
+                flip(segment.end.y),
+                hex(segment.color),
+                segment.thickness
+            ),
+            Element::Dot(dot) => writeln!(
+                group,
+                r#"    <circle cx="{:.3}" cy="{:.3}" r="{:.3}" fill="{}"/>"#,
+                dot.position.x,
+                flip(dot.position.y),
+                dot.radius,
+                hex(dot.color)
+            ),
+        }
+        .unwrap();
+    }
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.3}" height="{h:.3}" viewBox="{:.3} {:.3} {w:.3} {h:.3}" stroke-linecap="round">"#,
+        x,
+        y,
+        w = width,
+        h = height
+    )
+    .unwrap();
+    for (depth, elements) in groups {
+        writeln!(svg, r#"  <g id="depth-{}">"#, depth).unwrap();
+        svg.push_str(&elements);
+        svg.push_str("  </g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Creates missing parent directories
+pub fn write_svg(geometry: &Geometry, path: &Path) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, to_svg(geometry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l_system::Symbol;
+    use crate::turtle::{Turtle, TurtleInterpreter};
+
+    #[test]
+    fn groups_elements_by_stack_depth() {
+        let word = "F[+F@]F".chars().map(Symbol::from);
+        let svg = to_svg(&TurtleInterpreter::abop().interpret(Turtle::default(), word));
+
+        assert_eq!(svg.matches("<line").count(), 3);
+        assert_eq!(svg.matches("<circle").count(), 1);
+        let depth_1 = &svg[svg.find(r#"<g id="depth-1">"#).unwrap()..];
+        assert!(depth_1[..depth_1.find("</g>").unwrap()].contains("<circle"));
+        // The trunk ends 2 units up, which is y = -2 in SVG coordinates
+        assert!(svg.contains(r#"x2="0.000" y2="-2.000""#));
+    }
+}